@@ -1,11 +1,14 @@
 use ambassador::{delegatable_trait, Delegate};
 use clap::{Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::Result;
+use std::{path::PathBuf, process::ExitCode};
 
 use crate::commands::{
     backup::Backup, cln::Cln, copy::Copy, hog::Hog, paste::Paste, restore::Restore, update::Update,
     xtract::Xtract,
 };
+use crate::config::Config;
+use crate::utils::logging;
 
 #[derive(Parser)]
 #[command(
@@ -16,11 +19,11 @@ use crate::commands::{
 )]
 /// Main CLI
 pub struct Cli {
-    /// Verbosity level
-    #[clap(short, long, global = true, require_equals = true, value_name = "LEVEL", default_value = "info")]
-    pub verbosity: Verbosity,
+    /// Verbosity level [default: info]
+    #[clap(short, long, global = true, require_equals = true, value_name = "LEVEL")]
+    pub verbosity: Option<Verbosity>,
 
-    /// Suppress output
+    /// Only output errors
     #[clap(short, long, global = true)]
     pub quiet: bool,
 
@@ -41,31 +44,93 @@ pub struct Cli {
     pub prompt: Prompt,
 
     /// Answer "yes" to all prompts
-    #[clap(short, long, global = true)]
+    #[clap(short, long, global = true, conflicts_with = "no")]
     pub yes: bool,
 
     /// Answer "no" to all prompts
     #[clap(short, long, global = true)]
     pub no: bool,
 
+    /// Path to the configuration file
+    #[clap(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
     /// Command to be executed
     #[command(subcommand)]
     command: Commands,
 }
 
 impl Cli {
-    pub fn run(&mut self) -> Result<()> {
+    pub fn run(&mut self) -> Result<ExitCode> {
+        logging::init(self.verbosity, self.quiet, self.color());
+        let ctx = self.context()?;
+
         // Runnable::run cannot be public so cli.command.run() is not possible from main.rs
-        self.command.run()
+        self.command.run(&ctx)
+    }
+
+    /// Prompt behavior mode, `--yes` and `--no` taking precedence over `--prompt`
+    fn prompt(&self) -> Prompt {
+        if self.yes {
+            Prompt::Yes
+        } else if self.no {
+            Prompt::No
+        } else {
+            self.prompt
+        }
+    }
+
+    /// Colored output mode, `--no-color` only applying if `--color` is left to `auto`
+    fn color(&self) -> Color {
+        if self.no_color && matches!(self.color, Color::Auto) {
+            Color::Off
+        } else {
+            self.color
+        }
     }
+
+    /// Resolves global options into the context shared with every command
+    fn context(&self) -> Result<Context> {
+        Ok(Context {
+            dry_run: self.dry_run,
+            quiet: self.quiet,
+            color: self.color(),
+            prompt: self.prompt(),
+            config: Config::load(self.config.as_ref())?,
+        })
+    }
+}
+
+/// Global options that commands need to respect
+#[derive(Debug, Clone)]
+pub struct Context {
+    /// Simulate execution
+    pub dry_run: bool,
+
+    /// Only output errors
+    pub quiet: bool,
+
+    /// Colored output mode
+    pub color: Color,
+
+    /// Prompt behavior mode
+    pub prompt: Prompt,
+
+    /// Command defaults read from the configuration file
+    pub config: Config,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum Verbosity {
+    #[value(alias = "0")]
     Off,
+    #[value(alias = "1")]
     Error,
+    #[value(alias = "2")]
     Warn,
+    #[value(alias = "3")]
     Info,
+    #[value(alias = "4")]
     Debug,
 }
 
@@ -76,7 +141,7 @@ pub enum Color {
     Off,
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Prompt {
     Ask,
     Yes,
@@ -85,7 +150,14 @@ pub enum Prompt {
 
 #[delegatable_trait]
 pub trait Runnable {
-    fn run(&mut self) -> Result<()>;
+    fn run(&mut self, ctx: &Context) -> Result<ExitCode>;
+}
+
+// Allows boxing large commands inside the Commands enum
+impl<T: Runnable + ?Sized> Runnable for Box<T> {
+    fn run(&mut self, ctx: &Context) -> Result<ExitCode> {
+        (**self).run(ctx)
+    }
 }
 
 #[derive(Subcommand, Debug, Delegate)]
@@ -96,11 +168,11 @@ enum Commands {
     /// Restores a file or directory from a timestamped backup
     Restore(Restore),
     /// Removes non-ascii characters from file names
-    Cln(Cln),
+    Cln(Box<Cln>),
     /// Copy STDOUT to clipboard
     Copy(Copy),
     /// Print disk usage of a directory
-    Hog(Hog),
+    Hog(Box<Hog>),
     /// Paste clipboard to STDIN
     Paste(Paste),
     /// Archive extraction utility
@@ -108,3 +180,76 @@ enum Commands {
     /// Check for updates
     Update(Update),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::cln::Force;
+
+    #[test]
+    fn resolves_cln_conflicts_from_force_and_prompt() {
+        let prompts: [(&[&str], Prompt); 9] = [
+            (&[], Prompt::Ask),
+            (&["--prompt=ask"], Prompt::Ask),
+            (&["--prompt=yes"], Prompt::Yes),
+            (&["--prompt=no"], Prompt::No),
+            (&["-y"], Prompt::Yes),
+            (&["--yes"], Prompt::Yes),
+            (&["-n"], Prompt::No),
+            (&["--prompt=no", "-y"], Prompt::Yes),
+            (&["--prompt=yes", "--no"], Prompt::No),
+        ];
+        // conflict behavior for each --force given ask, yes and no prompt modes
+        let forces: [(&[&str], [Force; 3]); 8] = [
+            (&[], [Force::Auto, Force::Suffix, Force::Skip]),
+            (&["--force", "y"], [Force::Overwrite; 3]),
+            (&["--force", "overwrite"], [Force::Overwrite; 3]),
+            (&["-f", "n"], [Force::Skip; 3]),
+            (&["--force", "skip"], [Force::Skip; 3]),
+            (&["--force", "suffix"], [Force::Suffix; 3]),
+            (&["--force", "auto"], [Force::Auto; 3]),
+            (&["-f", "auto"], [Force::Auto; 3]),
+        ];
+
+        for (globals, prompt) in prompts {
+            for (force, expected) in &forces {
+                // global options may be given on either side of the command
+                for args in [
+                    [&["dsu"], globals, &["cln"], force].concat(),
+                    [&["dsu", "cln"], *force, globals].concat(),
+                ] {
+                    let cli = Cli::try_parse_from(&args).unwrap();
+                    assert_eq!(cli.prompt(), prompt, "prompt mode with {:?}", args);
+                    let Commands::Cln(cln) = &cli.command else {
+                        unreachable!()
+                    };
+                    let expected = match prompt {
+                        Prompt::Ask => expected[0],
+                        Prompt::Yes => expected[1],
+                        Prompt::No => expected[2],
+                    };
+                    assert_eq!(
+                        cln.conflict(cli.prompt()),
+                        expected,
+                        "conflicts with {:?}",
+                        args
+                    );
+                }
+            }
+        }
+
+        // answering yes alone never overwrites, as undoing can't restore the overwritten file
+        for (args, expected) in [
+            (&["dsu", "-y", "cln"][..], Force::Suffix),
+            (&["dsu", "-y", "cln", "--force", "y"], Force::Overwrite),
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let Commands::Cln(cln) = &cli.command else {
+                unreachable!()
+            };
+            assert_eq!(cln.conflict(cli.prompt()), expected, "conflicts with {:?}", args);
+        }
+
+        assert!(Cli::try_parse_from(["dsu", "-y", "-n", "cln"]).is_err());
+    }
+}
@@ -1,8 +1,11 @@
-use crate::{cli::Runnable, utils::file_keeper::validate_paths};
+use crate::{
+    cli::{Context, Runnable},
+    utils::file_keeper::validate_paths,
+};
 use clap::Args;
 use color_eyre::{eyre::bail, eyre::Result};
 use dialoguer::Confirm;
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, process::ExitCode};
 
 #[derive(Args, Debug)]
 pub struct Backup {
@@ -14,12 +17,13 @@ pub struct Backup {
 }
 
 impl Runnable for Backup {
-    fn run(&mut self) -> Result<()> {
+    fn run(&mut self, _ctx: &Context) -> Result<ExitCode> {
         if let Err(e) = validate_paths(&self.source, &mut self.target, false) {
             bail!("Backup validation failed: {}", e);
         }
 
-        self.backup()
+        self.backup()?;
+        Ok(ExitCode::SUCCESS)
     }
 }
 
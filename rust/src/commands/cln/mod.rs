@@ -0,0 +1,2510 @@
+mod report;
+mod undo;
+
+use crate::cli::{Context, Prompt, Runnable};
+use crate::config::Mappings;
+use crate::utils::matcher::{Matcher, match_names};
+use chrono::{
+    DateTime, Local,
+    format::{Item, StrftimeItems},
+};
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{Result, bail};
+use deunicode::deunicode_char;
+use dialoguer::{Confirm, Input, Select};
+use rayon::{ThreadPoolBuilder, prelude::*};
+use regex::Regex;
+use report::{Output, Report};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{metadata, read, read_to_string, rename, symlink_metadata},
+    io::{self, IsTerminal, Read, stdin},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Component, Path, PathBuf},
+    process::{self, ExitCode},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+use tracing::{debug, error, info, warn};
+use undo::UndoLog;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark, is_nfc};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Args, Debug)]
+pub struct Cln {
+    /// Paths to be cleaned
+    #[arg(default_value = ".")]
+    pub paths: Vec<PathBuf>,
+
+    /// Read the paths to clean from a file, one per line, `-` reading from stdin. Paths are not recursed into
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["paths", "recursive", "depth"])]
+    pub files_from: Option<PathBuf>,
+
+    /// Paths read with --files-from are NUL delimited instead
+    #[arg(long, short = '0', requires = "files_from")]
+    pub null: bool,
+
+    /// Also clean the directory components of the given paths, from the top down
+    #[arg(long)]
+    pub components: bool,
+
+    /// Clean directories recursively
+    #[arg(long, short = 'r')]
+    pub recursive: bool,
+
+    /// Recurse depth, 0 only cleans the given paths [default: 1, unlimited when recursive]
+    #[arg(long, short = 'e')]
+    pub depth: Option<usize>,
+
+    /// What to do when the clean name is already taken [default: derived from --prompt]
+    #[arg(long, short = 'f', value_enum)]
+    pub force: Option<Force>,
+
+    /// Replace non-ascii characters instead of transliterating them
+    #[arg(long)]
+    pub no_transliterate: bool,
+
+    /// Only convert filenames to their composed unicode form (NFC), without cleaning them
+    #[arg(long)]
+    pub normalize_only: bool,
+
+    /// Character to replace invalid characters with, an empty string removes them [default: style separator]
+    #[arg(long, short = 'k', value_name = "CHAR", value_parser = parse_replacement)]
+    pub replace_with: Option<String>,
+
+    /// Convert cleaned filenames to lowercase
+    #[arg(long, short = 'l')]
+    pub lowercase: bool,
+
+    /// Additional characters to allow in clean filenames, either as a set of characters
+    /// or as a regex character class such as `[ +]`
+    #[arg(long, short = 'a', value_name = "CHARS", value_parser = parse_allow)]
+    pub allow: Option<Regex>,
+
+    /// TOML or JSON file of `from = "to"` substitutions applied before cleaning
+    #[arg(long, value_name = "FILE")]
+    pub map: Option<PathBuf>,
+
+    /// Prefix the clean names of files with their modification date, formatted with a strftime pattern
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "%Y%m%d", value_parser = parse_date_format, conflicts_with = "normalize_only")]
+    pub prefix_date: Option<String>,
+
+    /// Naming style of cleaned filenames
+    #[arg(long, short = 's', default_value = "underscore")]
+    pub style: Style,
+
+    /// Also make filenames valid on Windows: rename reserved device names,
+    /// strip trailing dots and spaces, and replace characters invalid on NTFS
+    #[arg(long, short = 'w')]
+    pub windows_safe: bool,
+
+    /// Format used to report renames, `json` and `nul` being written to stdout
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+
+    /// Stop at the first error instead of carrying on with the remaining files
+    #[arg(long)]
+    pub abort_on_error: bool,
+
+    /// Only check for filenames that need cleaning, exiting with 1 if any is found
+    #[arg(long)]
+    pub check: bool,
+
+    /// Review every rename, with the option to edit the proposed name
+    #[arg(long, short = 'i')]
+    pub interactive: bool,
+
+    /// Maximum length in bytes of cleaned filenames, longer ones have their stem truncated
+    #[arg(long, value_name = "BYTES", default_value = "255", value_parser = clap::value_parser!(u16).range(16..))]
+    pub max_length: u16,
+
+    /// Also clean hidden files and directories found when recursing
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Also recurse into version control directories such as `.git`
+    #[arg(long)]
+    pub include_vcs: bool,
+
+    /// Compare names case-insensitively when looking for conflicts [default: detected per directory]
+    #[arg(long)]
+    pub case_insensitive: bool,
+
+    /// Recurse into symlinked directories
+    #[arg(long, short = 'L')]
+    pub follow_symlinks: bool,
+
+    /// Only clean entries matching the glob pattern, can be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip entries matching the glob pattern and don't recurse into them, can be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Only clean files with one of the comma separated extensions, directories are then left as is
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    pub ext: Vec<String>,
+
+    /// Also clean files without an extension when filtering by extension, same as an empty `--ext` entry
+    #[arg(long)]
+    pub no_ext: bool,
+
+    /// Revert the renames recorded in an undo log [default: most recent log]
+    #[arg(long, value_name = "LOG")]
+    pub undo: Option<Option<PathBuf>>,
+
+    /// Number of threads used to scan directories [default: number of CPUs]
+    #[arg(long, short = 'j', value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+
+    #[arg(skip)]
+    filters: Filters,
+
+    #[arg(skip)]
+    separator_runs: OnceLock<Regex>,
+
+    /// Substitutions from `--map` and the config file, longest first so that `&&` takes precedence over `&`
+    #[arg(skip)]
+    mappings: Vec<(String, String)>,
+
+    #[arg(skip)]
+    dry_run: bool,
+
+    #[arg(skip)]
+    on_conflict: Force,
+
+    #[arg(skip)]
+    can_prompt: bool,
+}
+
+/// Compiled `--include` and `--exclude` patterns
+#[derive(Debug, Default)]
+struct Filters {
+    include: Option<Matcher>,
+    exclude: Option<Matcher>,
+}
+
+/// Entry found while scanning the paths to clean
+///
+/// Scanning happens in parallel ahead of cleaning, which then renames entries one at a time
+/// so that conflicts between them are always detected.
+#[derive(Debug)]
+struct Node {
+    path: PathBuf,
+    kind: NodeKind,
+    is_dir: bool,
+    /// Modification time as scanned, before any of the contents of a directory are renamed
+    modified: Option<SystemTime>,
+    /// Errors found while reading the contents of a directory
+    errors: Vec<(String, io::ErrorKind)>,
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Missing,
+    Excluded,
+    /// Hidden or version control entry found when recursing
+    Ignored,
+    /// Entry to be cleaned, along with the contents to clean beforehand if it's a directory
+    Entry(Vec<Node>),
+}
+
+/// Contents of a directory, listed once when first looking for conflicts in it
+#[derive(Debug)]
+struct Listing {
+    /// Whether the filesystem treats names differing only in case as the same file
+    case_insensitive: bool,
+    names: Vec<OsString>,
+}
+
+/// Path a file should be renamed to
+struct Target {
+    path: PathBuf,
+    /// Whether the clean name was already taken
+    conflict: bool,
+    /// Whether a numeric suffix had to be appended to the clean name
+    suffixed: bool,
+}
+
+/// Bookkeeping of a single run
+#[derive(Debug, Default)]
+struct State {
+    /// Paths renamed to during this run, or planned to be when dry running
+    claimed: HashSet<PathBuf>,
+    /// Paths renamed from during this run, or planned to be when dry running
+    vacated: HashSet<PathBuf>,
+    /// Directories looked for conflicts in
+    listings: HashMap<PathBuf, Listing>,
+    /// Files left as is because their clean name is shared with other files
+    held: HashSet<PathBuf>,
+    /// Number of entries considered for renaming
+    examined: usize,
+    /// Number of renamed files
+    renamed: usize,
+    /// Number of files whose name was already clean
+    clean: usize,
+    /// Number of files skipped because their clean name was already taken
+    conflicts: usize,
+    /// Number of files skipped for any other reason, e.g. when declined
+    skipped: usize,
+    /// Number of errors found
+    errors: usize,
+    /// Exit code for the errors found, specific if they were all of the same kind
+    failure: Option<u8>,
+    /// Number of renamed files that needed a numeric suffix
+    suffixed: usize,
+    /// Number of renamed files that had to be truncated
+    truncated: usize,
+    /// Number of entries left untouched by `--include` and `--exclude`
+    filtered: usize,
+    /// Number of hidden and version control entries left untouched
+    ignored: usize,
+    /// Log of the renames performed, absent when dry running
+    log: Option<UndoLog>,
+    /// Report of the renames performed, or planned when dry running
+    report: Report,
+    /// Whether all remaining renames were accepted when reviewing
+    accept_all: bool,
+    /// Whether the user asked to stop when reviewing
+    quit: bool,
+}
+
+/// Answer to a proposed rename when reviewing interactively
+enum Review {
+    Yes,
+    No,
+    All,
+    Edit(String),
+    Quit,
+}
+
+/// Extensions made of more than one part that are kept together when splitting a filename
+const MULTIPART_EXTENSIONS: [&str; 6] = [
+    "tar.gz", "tar.bz2", "tar.xz", "tar.zst", "tar.lz4", "tar.7z",
+];
+
+/// Exit code of runs that skipped files because their clean name was taken, but found no errors
+const EXIT_CONFLICTS: u8 = 7;
+
+/// Exit code of runs, other than checks, that found every entry already clean and had nothing to do
+const EXIT_ALREADY_CLEAN: u8 = 6;
+
+/// Version control directories, never recursed into unless asked to
+const VCS_DIRECTORIES: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Characters that can't be part of a filename on NTFS
+const WINDOWS_INVALID: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names that Windows reserves regardless of extension
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Force {
+    /// Overwrite the existing file
+    #[value(name = "y", alias = "overwrite")]
+    Overwrite,
+    /// Skip renaming the file
+    #[value(name = "n", alias = "skip")]
+    Skip,
+    /// Append a numeric suffix to the new name
+    Suffix,
+    /// Ask before overwriting, appending a numeric suffix when unable to ask
+    #[default]
+    Auto,
+}
+
+impl Force {
+    /// Conflict behavior when `--force` isn't given, following the global prompt mode
+    ///
+    /// Answering yes never overwrites, since `--undo` can't bring an overwritten file back
+    fn from_prompt(prompt: Prompt) -> Self {
+        match prompt {
+            Prompt::Ask => Force::Auto,
+            Prompt::Yes => Force::Suffix,
+            Prompt::No => Force::Skip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Style {
+    /// Replace invalid characters with underscores
+    Underscore,
+    /// Replace invalid characters with dashes
+    Dash,
+    /// Same as dash, but lowercase and without leading or trailing separators
+    Slug,
+}
+
+impl Style {
+    fn separator(&self) -> &'static str {
+        match self {
+            Style::Underscore => "_",
+            Style::Dash | Style::Slug => "-",
+        }
+    }
+}
+
+/// Whether `c` only modifies the character it follows within a grapheme cluster,
+/// such as combining marks, zero width joiners, variation selectors and emoji skin tones.
+fn is_modifier(c: char) -> bool {
+    is_combining_mark(c)
+        || matches!(c, '\u{200c}' | '\u{200d}' | '\u{fe00}'..='\u{fe0f}' | '\u{1f3fb}'..='\u{1f3ff}')
+}
+
+/// Directory containing `path`, `.` for relative paths made of a single component
+fn parent_dir(path: &Path) -> Option<&Path> {
+    match path.parent()? {
+        parent if parent.as_os_str().is_empty() => Some(Path::new(".")),
+        parent => Some(parent),
+    }
+}
+
+/// Whether two paths differ only by the case of their filename
+fn is_case_only(path: &Path, other: &Path) -> bool {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let other = other.file_name().unwrap_or_default().to_string_lossy();
+
+    filename != other && filename.to_lowercase() == other.to_lowercase()
+}
+
+fn parse_replacement(value: &str) -> Result<String, String> {
+    let mut chars = value.chars();
+
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok(String::new()),
+        (Some(c), None) if !['/', '\0'].contains(&c) => Ok(c.to_string()),
+        (Some(c), None) => Err(format!("{:?} is not a valid filename character", c)),
+        _ => Err("expected a single character or an empty string".to_string()),
+    }
+}
+
+/// Parses a strftime pattern for `--prefix-date`, which must give a non-empty filename component
+fn parse_date_format(value: &str) -> Result<String, String> {
+    if StrftimeItems::new(value).any(|item| item == Item::Error) {
+        return Err(format!("{:?} is not a valid strftime pattern", value));
+    }
+
+    let sample = Local::now().format(value).to_string();
+    if sample.is_empty() || sample.contains(['/', '\0']) {
+        return Err(format!("{:?} doesn't give a valid filename prefix", value));
+    }
+
+    Ok(value.to_string())
+}
+
+/// Rebuilds `path` against the new names of its `renamed` components, given by their rebased path.
+fn rebase(path: &Path, renamed: &HashMap<PathBuf, PathBuf>) -> PathBuf {
+    let mut rebased = PathBuf::new();
+    for component in path.components() {
+        rebased.push(component);
+        if let Some(new) = renamed.get(&rebased) {
+            rebased = new.clone();
+        }
+    }
+
+    rebased
+}
+
+/// First 8 hex digits of the SHA-256 of a filename
+fn short_hash(filename: &str) -> String {
+    Sha256::digest(filename.as_bytes())[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Largest index not greater than `index` that lies on a character boundary of `s`
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    (0..=index.min(s.len()))
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+fn parse_allow(value: &str) -> Result<Regex, String> {
+    // a plain set of characters is turned into a character class
+    let class = if value.starts_with('[') {
+        value.to_string()
+    } else {
+        format!("[{}]", regex::escape(value))
+    };
+
+    let pattern = Regex::new(&format!("^(?:{})$", class))
+        .map_err(|err| format!("invalid character pattern: {}", err))?;
+
+    if pattern.is_match("/") || pattern.is_match("\0") {
+        return Err("path separators and NUL characters can't be allowed".to_string());
+    }
+
+    Ok(pattern)
+}
+
+impl Runnable for Cln {
+    fn run(&mut self, ctx: &Context) -> Result<ExitCode> {
+        // checking is a dry run that never prompts
+        self.dry_run = ctx.dry_run || self.check;
+        self.can_prompt = ctx.prompt == Prompt::Ask && stdin().is_terminal() && !self.check;
+        self.on_conflict = self.conflict(ctx.prompt);
+
+        if self.check && self.interactive {
+            bail!("--check and --interactive can't be used together");
+        }
+
+        if self.interactive && !stdin().is_terminal() {
+            bail!("Interactive mode requires a terminal");
+        }
+
+        if let Some(log) = &self.undo {
+            self.undo(log.as_ref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if let Some(file) = &self.files_from {
+            self.paths = Self::read_paths(file, self.null)?;
+        }
+
+        if self.allow.is_none()
+            && let Some(allow) = &ctx.config.cln.allow
+        {
+            match parse_allow(allow) {
+                Ok(allow) => self.allow = Some(allow),
+                Err(err) => bail!("Invalid cln.allow in config file: {}", err),
+            }
+        }
+
+        self.load_mappings(&ctx.config.cln.map)?;
+
+        if let Some(c) = self.replacement().chars().next()
+            && !self.is_allowed(c)
+        {
+            bail!("Replacement character {:?} is not allowed in filenames", c);
+        }
+
+        self.filters = Filters {
+            include: Matcher::new(&self.include)?,
+            exclude: Matcher::new(&self.exclude)?,
+        };
+
+        let mut state = State {
+            log: (!self.dry_run).then(UndoLog::create).transpose()?,
+            report: Report::new(self.output, self.dry_run),
+            ..Default::default()
+        };
+
+        if self.components {
+            self.paths = self.clean_components(&self.paths, &mut state)?;
+        }
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.jobs.map_or(0, usize::from))
+            .build()?;
+        let visited = Mutex::new(HashSet::new());
+        let nodes: Vec<Node> = pool.install(|| {
+            self.paths
+                .par_iter()
+                .map(|path| self.scan(path, path, 0, &visited))
+                .collect()
+        });
+
+        // explicit paths may be in the same directory as well
+        self.plan(
+            self.paths.iter().map(PathBuf::as_path).zip(&nodes),
+            &mut state,
+        )?;
+
+        for (path, node) in self.paths.iter().zip(nodes) {
+            self.clean_files(path, vec![node], &mut state)?;
+        }
+
+        std::mem::take(&mut state.report).finish()?;
+        self.print_summary(&state);
+
+        if let Some(log) = state.log.and_then(UndoLog::finish) {
+            info!("Undo log written to {:?}", log);
+        }
+
+        if let Some(code) = state.failure {
+            return Ok(ExitCode::from(code));
+        }
+
+        // when checking, files that would be renamed are a failure
+        if self.check && state.renamed > 0 {
+            Ok(ExitCode::FAILURE)
+        } else if state.conflicts > 0 {
+            Ok(ExitCode::from(EXIT_CONFLICTS))
+        } else if !self.check && state.clean == state.examined {
+            Ok(ExitCode::from(EXIT_ALREADY_CLEAN))
+        } else {
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+impl Cln {
+    /// Scans `path`, found at `depth` below `root`, the path originally given to clean,
+    /// along with the contents to be cleaned if it's a directory.
+    fn scan(
+        &self,
+        root: &Path,
+        path: &Path,
+        depth: usize,
+        visited: &Mutex<HashSet<(u64, u64)>>,
+    ) -> Node {
+        let mut node = Node {
+            path: path.to_path_buf(),
+            kind: NodeKind::Missing,
+            is_dir: false,
+            modified: None,
+            errors: Vec::new(),
+        };
+
+        // symlinks themselves are renamed, never their targets
+        let metadata = if self.follow_symlinks {
+            metadata(path).or_else(|_| symlink_metadata(path))
+        } else {
+            symlink_metadata(path)
+        };
+
+        let Ok(metadata) = metadata else {
+            return node;
+        };
+
+        let (filename, relative) = match_names(root, path);
+
+        // paths given explicitly are always cleaned, however hidden
+        if depth > 0 && self.is_ignored(filename) {
+            node.kind = NodeKind::Ignored;
+            return node;
+        }
+
+        // excluded directories are not recursed into
+        if let Some(exclude) = &self.filters.exclude
+            && exclude.is_match(filename, relative)
+        {
+            node.kind = NodeKind::Excluded;
+            return node;
+        }
+
+        // break symlink loops by never visiting the same directory twice
+        let is_new_dir = metadata.is_dir()
+            && (!self.follow_symlinks
+                || visited
+                    .lock()
+                    .unwrap()
+                    .insert((metadata.dev(), metadata.ino())));
+
+        let mut children = Vec::new();
+        if is_new_dir && self.should_recurse(depth) {
+            match path.read_dir() {
+                Ok(entries) => {
+                    let mut new_paths = Vec::new();
+                    for entry in entries {
+                        match entry {
+                            Ok(entry) => new_paths.push(entry.path()),
+                            Err(err) => node.errors.push((
+                                format!("Failed to read entry of {:?}: {}", path, err),
+                                err.kind(),
+                            )),
+                        }
+                    }
+
+                    children = new_paths
+                        .par_iter()
+                        .map(|new_path| self.scan(root, new_path, depth + 1, visited))
+                        .collect();
+                }
+                Err(err) => node.errors.push((
+                    format!("Failed to read directory {:?}: {}", path, err),
+                    err.kind(),
+                )),
+            }
+        }
+
+        node.kind = NodeKind::Entry(children);
+        node.is_dir = metadata.is_dir();
+        node.modified = metadata.modified().ok();
+        node
+    }
+
+    /// Cleans the directory components of `paths` from the top down, returning the paths rebased
+    /// onto the renamed directories.
+    ///
+    /// Directories shared by several paths are only cleaned once, later paths going through
+    /// their new names. Nothing is rebased when dry running, as nothing has been renamed.
+    fn clean_components(&self, paths: &[PathBuf], state: &mut State) -> Result<Vec<PathBuf>> {
+        let mut renamed: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut rebased = Vec::new();
+
+        for path in paths {
+            // missing paths are reported once scanned
+            let Some(filename) = path.file_name() else {
+                rebased.push(path.clone());
+                continue;
+            };
+            if symlink_metadata(rebase(path, &renamed)).is_err() {
+                rebased.push(path.clone());
+                continue;
+            }
+
+            let mut current = PathBuf::new();
+            for component in path.parent().into_iter().flat_map(Path::components) {
+                current.push(component);
+                if !matches!(component, Component::Normal(_)) || state.quit {
+                    continue;
+                }
+
+                if let Some(new) = renamed.get(&current) {
+                    current = new.clone();
+                    continue;
+                }
+
+                let original = current.clone();
+                if let Some(new) = self.clean_file(&current, None, state)?
+                    && !self.dry_run
+                {
+                    current = new;
+                }
+                renamed.insert(original, current.clone());
+            }
+
+            rebased.push(current.join(filename));
+        }
+
+        // paths may themselves be directories of other paths, renamed once those were cleaned
+        Ok(rebased
+            .into_iter()
+            .map(|path| rebase(&path, &renamed))
+            .collect())
+    }
+
+    /// Why an entry found below `root` is left untouched by `--include` or `--ext`, `None` if it isn't.
+    ///
+    /// Directories not included are still recursed into to find matching entries.
+    fn filter_reason(&self, root: &Path, path: &Path, is_dir: bool) -> Option<&'static str> {
+        let (filename, relative) = match_names(root, path);
+
+        match &self.filters.include {
+            Some(include) if !include.is_match(filename, relative) => Some("not included"),
+            _ if !self.matches_ext(filename, is_dir) => Some("extension not selected"),
+            _ => None,
+        }
+    }
+
+    /// Whether an entry passes the `--ext` filter, which directories never do.
+    ///
+    /// Both whole multi-part extensions and their last part match, so `gz` matches `archive.tar.gz`.
+    fn matches_ext(&self, filename: &Path, is_dir: bool) -> bool {
+        if self.ext.is_empty() && !self.no_ext {
+            return true;
+        }
+
+        if is_dir {
+            return false;
+        }
+
+        let filename = filename.to_string_lossy();
+        let matches = |ext: &str| {
+            self.ext
+                .iter()
+                .any(|wanted| wanted.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        };
+
+        match Self::split_extension(&filename).1 {
+            Some(ext) => matches(ext) || ext.rsplit('.').next().is_some_and(matches),
+            None => self.no_ext || matches(""),
+        }
+    }
+
+    /// Whether an entry found when recursing should be left alone as hidden or version control
+    fn is_ignored(&self, filename: &Path) -> bool {
+        let filename = filename.to_string_lossy();
+        if VCS_DIRECTORIES.contains(&filename.as_ref()) {
+            return !self.include_vcs;
+        }
+
+        !self.hidden && filename.starts_with('.')
+    }
+
+    /// Cleans the scanned `nodes` found below `root`, the path originally given to clean.
+    fn clean_files(&self, root: &Path, nodes: Vec<Node>, state: &mut State) -> Result<()> {
+        self.plan(nodes.iter().map(|node| (root, node)), state)?;
+
+        for node in nodes {
+            if state.quit {
+                break;
+            }
+
+            let prefix = self.date_prefix(&node);
+            let path = node.path;
+            let children = match node.kind {
+                NodeKind::Missing => {
+                    error!("File does not exist: {:?}", path);
+                    self.fail(state, io::ErrorKind::NotFound);
+                    continue;
+                }
+                NodeKind::Excluded => {
+                    debug!("Skipping {:?}, excluded", path);
+                    state.filtered += 1;
+                    continue;
+                }
+                NodeKind::Ignored => {
+                    debug!("Skipping {:?}, hidden or version control", path);
+                    state.ignored += 1;
+                    continue;
+                }
+                NodeKind::Entry(children) => children,
+            };
+
+            for (err, kind) in node.errors {
+                error!("{}", err);
+                self.fail(state, kind);
+            }
+
+            // clean the contents of a directory before the directory itself,
+            // so that its children are always visited under their current path
+            self.clean_files(root, children, state)?;
+            if state.quit {
+                break;
+            }
+
+            // paths such as `.`, `..` or `/` can't be renamed, nor filtered out
+            if path.file_name().is_none() {
+                continue;
+            }
+
+            match self.filter_reason(root, &path, node.is_dir) {
+                Some(reason) => {
+                    debug!("Skipping {:?}, {}", path, reason);
+                    state.filtered += 1;
+                }
+                None => {
+                    self.clean_file(&path, prefix.as_deref(), state)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Date `--prefix-date` prepends to the name of a scanned file, if any.
+    ///
+    /// Directories are left without one, as renaming their contents changes their modification time.
+    fn date_prefix(&self, node: &Node) -> Option<String> {
+        let pattern = self.prefix_date.as_ref()?;
+        if node.is_dir {
+            return None;
+        }
+
+        let date = DateTime::<Local>::from(node.modified?)
+            .format(pattern)
+            .to_string();
+        Some(self.clean_part(&date)).filter(|prefix| !prefix.is_empty())
+    }
+
+    /// Whether `filename` lacks the date `prefix`, followed by a separator.
+    ///
+    /// Hidden files are never prefixed, which would make them visible.
+    fn lacks_prefix(&self, filename: &str, prefix: &str) -> bool {
+        !filename.starts_with('.')
+            && !filename
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with(self.separator()))
+    }
+
+    /// Proposes a clean name for `filename`, prefixed with the date `prefix` if given,
+    /// along with whether it was truncated and whether it fell back to a name derived from the original one.
+    fn propose(&self, filename: &str, prefix: Option<&str>) -> (String, bool, bool) {
+        let (clean_filename, clean_stem) = self.clean_name(filename);
+
+        // names without a single character worth keeping, e.g. made only of emoji, fall back to one
+        // derived from the original name, so that they stay unique and can be told apart
+        let fallback = !self.normalize_only && self.is_blank(&clean_stem);
+        let clean_filename = if fallback {
+            let extension = &clean_filename[clean_stem.len()..];
+            format!("unnamed-{}{}", short_hash(filename), extension)
+        } else {
+            clean_filename
+        };
+
+        // names already starting with the exact date aren't prefixed twice
+        let clean_filename = match prefix {
+            Some(prefix) if self.lacks_prefix(&clean_filename, prefix) => {
+                format!("{}{}{}", prefix, self.separator(), clean_filename)
+            }
+            _ => clean_filename,
+        };
+
+        let (clean_filename, truncated) = self.truncate(clean_filename, filename);
+        (clean_filename, truncated, fallback)
+    }
+
+    /// Looks for files among `nodes`, given along with the path originally given to clean they were found under,
+    /// whose clean names would be the same, reporting them before anything is renamed.
+    ///
+    /// All but the first file of each group get a numeric suffix as they are renamed,
+    /// unless asked to skip the whole group instead.
+    fn plan<'a>(
+        &self,
+        nodes: impl Iterator<Item = (&'a Path, &'a Node)>,
+        state: &mut State,
+    ) -> Result<()> {
+        let mut groups: Vec<(PathBuf, Vec<&Path>)> = Vec::new();
+
+        for (root, node) in nodes {
+            if !matches!(node.kind, NodeKind::Entry(_))
+                || self.filter_reason(root, &node.path, node.is_dir).is_some()
+            {
+                continue;
+            }
+
+            let Some(filename) = node.path.file_name() else {
+                continue;
+            };
+            let filename = filename.to_string_lossy();
+            let prefix = self.date_prefix(node);
+            if self.dirt(&filename, prefix.as_deref()).is_none() {
+                continue;
+            }
+
+            let (clean_filename, _, _) = self.propose(&filename, prefix.as_deref());
+            if clean_filename == filename {
+                continue;
+            }
+
+            let clean_path = node.path.with_file_name(&clean_filename);
+            let insensitive =
+                parent_dir(&clean_path).is_some_and(|parent| self.is_insensitive(parent, state));
+            let same_path = |a: &Path, b: &Path| {
+                if insensitive {
+                    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+                } else {
+                    a == b
+                }
+            };
+
+            match groups
+                .iter_mut()
+                .find(|(target, _)| same_path(target, &clean_path))
+            {
+                Some((_, paths)) => paths.push(&node.path),
+                None => groups.push((clean_path, vec![&node.path])),
+            }
+        }
+
+        for (target, paths) in groups.into_iter().filter(|(_, paths)| paths.len() > 1) {
+            warn!(
+                "{} files would be renamed to {:?}: {}",
+                paths.len(),
+                target,
+                paths
+                    .iter()
+                    .map(|path| format!("{:?}", path))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+
+            if !self.can_prompt || self.interactive || !matches!(self.on_conflict, Force::Auto) {
+                continue;
+            }
+
+            let choice = Select::new()
+                .with_prompt(format!(
+                    "How should the files renamed to {:?} be told apart?",
+                    target
+                ))
+                .items(&[
+                    "Append a numeric suffix to all but the first",
+                    "Skip all of them",
+                ])
+                .default(0)
+                .interact()?;
+
+            if choice == 1 {
+                state.held.extend(paths.into_iter().map(Path::to_path_buf));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cleans the name of the file at `path`, prefixing it with the date `prefix` if given.
+    ///
+    /// Returns the path the file was renamed to, or would be when dry running.
+    fn clean_file(
+        &self,
+        path: &PathBuf,
+        prefix: Option<&str>,
+        state: &mut State,
+    ) -> Result<Option<PathBuf>> {
+        let filename = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            // paths such as `.`, `..` or `/` can't be renamed
+            None => return Ok(None),
+        };
+
+        // explicit paths may also be found below one another, or be a directory component of one
+        if state.vacated.contains(path) {
+            debug!("Skipping {:?}, already renamed", path);
+            return Ok(None);
+        }
+
+        state.examined += 1;
+
+        // nothing to do if the filename is already clean
+        let Some(dirt) = self.dirt(&filename, prefix) else {
+            debug!("{:?} is already clean", path);
+            state.clean += 1;
+            return Ok(None);
+        };
+        debug!("{:?} needs cleaning as {}", path, dirt);
+
+        let (mut clean_filename, truncated, fallback) = self.propose(&filename, prefix);
+
+        // the replacement character may already be part of the original name
+        if clean_filename == filename {
+            debug!(
+                "{:?} is already clean, cleaning it gives the same name",
+                path
+            );
+            state.clean += 1;
+            return Ok(None);
+        }
+
+        if state.held.contains(path) {
+            debug!(
+                "Skipping {:?}, its clean name is shared with other files",
+                path
+            );
+            state.skipped += 1;
+            return Ok(None);
+        }
+
+        // confirm fallback names when able to ask, reviewing already prompts for every file
+        if fallback
+            && self.can_prompt
+            && matches!(self.on_conflict, Force::Auto)
+            && !self.review_needed(state)
+            && !Confirm::new()
+                .with_prompt(format!(
+                    "File {:?} has no characters to keep, rename to {:?}?",
+                    filename, clean_filename
+                ))
+                .interact()?
+        {
+            state.skipped += 1;
+            return Ok(None);
+        }
+
+        let target = loop {
+            let Some(target) = self.resolve_target(path, &clean_filename, state)? else {
+                state.conflicts += 1;
+                return Ok(None);
+            };
+
+            if !self.review_needed(state) {
+                break target;
+            }
+
+            match self.review(path, &target.path)? {
+                Review::Yes => break target,
+                Review::No => {
+                    state.skipped += 1;
+                    return Ok(None);
+                }
+                Review::All => {
+                    state.accept_all = true;
+                    break target;
+                }
+                // edited names go through conflict resolution and review again
+                Review::Edit(name) => clean_filename = name,
+                Review::Quit => {
+                    state.quit = true;
+                    state.skipped += 1;
+                    return Ok(None);
+                }
+            }
+        };
+
+        let clean_path = target.path;
+        if !self.dry_run {
+            let case_insensitive = parent_dir(&clean_path)
+                .and_then(|parent| state.listings.get(parent))
+                .is_some_and(|listing| listing.case_insensitive);
+
+            if let Err(err) = self.rename(path, &clean_path, case_insensitive) {
+                error!("Failed to rename {:?} to {:?}: {}", path, clean_path, err);
+                self.fail(state, err.kind());
+                return Ok(None);
+            }
+
+            if let Some(log) = &mut state.log
+                && let Err(err) = log.record(path, &clean_path)
+            {
+                error!("Failed to record rename in undo log: {}", err);
+                self.fail(state, io::ErrorKind::Other);
+            }
+        }
+
+        let filename: String = filename.nfc().collect();
+        let (_, mapped) = self.apply_map(&filename);
+        state
+            .report
+            .rename(path, &clean_path, target.conflict, &mapped)?;
+        state.renamed += 1;
+        if target.suffixed {
+            state.suffixed += 1;
+        }
+        if truncated {
+            state.truncated += 1;
+        }
+        state.vacated.insert(path.clone());
+        state.claimed.insert(clean_path.clone());
+
+        Ok(Some(clean_path))
+    }
+
+    /// Resolves the path `path` should be renamed to given its clean filename,
+    /// handling conflicts with existing files according to `--force`.
+    ///
+    /// Returns `None` if the file should be skipped.
+    fn resolve_target(
+        &self,
+        path: &Path,
+        clean_filename: &str,
+        state: &mut State,
+    ) -> Result<Option<Target>> {
+        let clean_path = path.with_file_name(clean_filename);
+
+        let Some(conflict) = self.find_conflict(path, &clean_path, state) else {
+            return Ok(Some(Target {
+                path: clean_path,
+                conflict: false,
+                suffixed: false,
+            }));
+        };
+
+        let overwrite = Target {
+            path: clean_path,
+            conflict: true,
+            suffixed: false,
+        };
+
+        // never overwrite a file that was renamed by this same run
+        let renamed = state.claimed.contains(&conflict);
+
+        match self.on_conflict {
+            Force::Skip => {
+                debug!("File {:?} already exists, skipping {:?}", conflict, path);
+                Ok(None)
+            }
+            Force::Overwrite if !renamed => Ok(Some(overwrite)),
+            Force::Auto if !renamed && self.can_prompt => {
+                // prompt for confirmation if exists
+                let confirmed = Confirm::new()
+                    .with_prompt(format!("Overwrite existing file at {:?}?", conflict))
+                    .interact()?;
+
+                Ok(confirmed.then_some(overwrite))
+            }
+            // files renamed by this run are suffixed instead, as is anything that couldn't be asked
+            Force::Overwrite | Force::Auto | Force::Suffix => Ok(Some(Target {
+                path: self.suffixed_path(path, clean_filename, state),
+                conflict: true,
+                suffixed: true,
+            })),
+        }
+    }
+
+    fn review_needed(&self, state: &State) -> bool {
+        self.interactive && !state.accept_all
+    }
+
+    /// Asks what to do with a proposed rename when reviewing interactively.
+    fn review(&self, path: &Path, clean_path: &Path) -> Result<Review> {
+        let answer: String = Input::new()
+            .with_prompt(format!(
+                "Rename {:?} -> {:?}? [y]es, [n]o, [a]ll, [e]dit, [q]uit",
+                path, clean_path
+            ))
+            .validate_with(|answer: &String| match answer.as_str() {
+                "y" | "n" | "a" | "e" | "q" => Ok(()),
+                _ => Err("expected one of y, n, a, e or q"),
+            })
+            .interact_text()?;
+
+        Ok(match answer.as_str() {
+            "y" => Review::Yes,
+            "a" => Review::All,
+            "q" => Review::Quit,
+            "e" => {
+                let filename = clean_path.file_name().unwrap_or_default().to_string_lossy();
+                let name: String = Input::new()
+                    .with_prompt("New name")
+                    .with_initial_text(filename)
+                    .validate_with(|name: &String| {
+                        if name.is_empty() || name == "." || name == ".." {
+                            Err("name can't be empty, . or ..")
+                        } else if name.contains(['/', '\0']) {
+                            Err("name can't contain path separators or NUL characters")
+                        } else {
+                            Ok(())
+                        }
+                    })
+                    .interact_text()?;
+
+                Review::Edit(name)
+            }
+            _ => Review::No,
+        })
+    }
+
+    /// Records an error, stopping the run with `--abort-on-error`.
+    fn fail(&self, state: &mut State, kind: io::ErrorKind) {
+        let code = match kind {
+            io::ErrorKind::PermissionDenied => 3,
+            io::ErrorKind::NotFound => 4,
+            _ => 1,
+        };
+
+        state.errors += 1;
+        state.failure = Some(match state.failure {
+            Some(failure) if failure != code => 1,
+            _ => code,
+        });
+
+        if self.abort_on_error {
+            state.quit = true;
+        }
+    }
+
+    fn print_summary(&self, state: &State) {
+        info!("Examined: {}", state.examined);
+        info!(
+            "{}: {} ({} with a numeric suffix, {} truncated)",
+            if self.dry_run {
+                "Would rename"
+            } else {
+                "Renamed"
+            },
+            state.renamed,
+            state.suffixed,
+            state.truncated
+        );
+        info!("Already clean: {}", state.clean);
+        info!("Skipped (conflict): {}", state.conflicts);
+        if state.skipped > 0 {
+            info!("Skipped (other): {}", state.skipped);
+        }
+        if state.filtered > 0 {
+            info!("Filtered out: {}", state.filtered);
+        }
+        if state.ignored > 0 {
+            info!("Skipped (hidden or VCS): {}", state.ignored);
+        }
+        info!("Errors: {}", state.errors);
+        if self.abort_on_error && state.errors > 0 {
+            info!("Aborted after the first error");
+        }
+    }
+
+    /// Loads the substitutions from the config file and `--map`, the latter taking precedence.
+    ///
+    /// Keys are normalized like filenames are, so keys written twice, even differently encoded, are an error.
+    fn load_mappings(&mut self, defaults: &Mappings) -> Result<()> {
+        let validate = |mappings: &Mappings, source: &str| -> Result<HashMap<String, String>> {
+            let mut validated = HashMap::new();
+            for (from, to) in &mappings.0 {
+                let from: String = from.nfc().collect();
+                if from.is_empty() {
+                    bail!("Empty mapping key in {}", source);
+                }
+
+                if to.contains(['/', '\0']) {
+                    bail!(
+                        "Invalid mapping for {:?} in {}: {:?} can't be part of a filename",
+                        from,
+                        source,
+                        to
+                    );
+                }
+
+                if validated.insert(from.clone(), to.clone()).is_some() {
+                    bail!("Conflicting mappings for {:?} in {}", from, source);
+                }
+            }
+            Ok(validated)
+        };
+
+        let mut mappings = validate(defaults, "config file")?;
+
+        if let Some(file) = &self.map {
+            let contents = match read_to_string(file) {
+                Ok(contents) => contents,
+                Err(err) => bail!("Failed to read map file {:?}: {}", file, err),
+            };
+
+            let parsed = if file.extension().is_some_and(|ext| ext == "json") {
+                serde_json::from_str(&contents).map_err(|err| err.to_string())
+            } else {
+                toml::from_str(&contents).map_err(|err| err.to_string())
+            };
+
+            let parsed = match parsed {
+                Ok(parsed) => parsed,
+                Err(err) => bail!("Failed to parse map file {:?}: {}", file, err),
+            };
+
+            mappings.extend(validate(&parsed, &format!("{:?}", file))?);
+        }
+
+        self.mappings = mappings.into_iter().collect();
+        self.mappings
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        Ok(())
+    }
+
+    /// Applies the `--map` substitutions to `text`, returning the result along with the substitutions applied.
+    fn apply_map(&self, text: &str) -> (String, Vec<&(String, String)>) {
+        let mut mapped = String::with_capacity(text.len());
+        let mut applied: Vec<&(String, String)> = Vec::new();
+        let mut rest = text;
+
+        'outer: while let Some(c) = rest.chars().next() {
+            for mapping in &self.mappings {
+                if let Some(after) = rest.strip_prefix(mapping.0.as_str()) {
+                    mapped.push_str(&mapping.1);
+                    if !applied.contains(&mapping) {
+                        applied.push(mapping);
+                    }
+                    rest = after;
+                    continue 'outer;
+                }
+            }
+
+            mapped.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+
+        (mapped, applied)
+    }
+
+    /// Reads paths delimited by newlines or NULs from `file`, or stdin if `-`.
+    ///
+    /// Paths are sorted deepest first, so that the contents of listed directories are renamed
+    /// before the directories themselves.
+    fn read_paths(file: &Path, null: bool) -> Result<Vec<PathBuf>> {
+        let contents = if file == Path::new("-") {
+            let mut contents = Vec::new();
+            stdin().lock().read_to_end(&mut contents)?;
+            contents
+        } else {
+            match read(file) {
+                Ok(contents) => contents,
+                Err(err) => bail!("Failed to read paths from {:?}: {}", file, err),
+            }
+        };
+
+        let delimiter = if null { b'\0' } else { b'\n' };
+        let mut paths: Vec<PathBuf> = contents
+            .split(|&byte| byte == delimiter)
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+            .collect();
+
+        paths.sort_by_key(|path| Reverse(path.components().count()));
+        Ok(paths)
+    }
+
+    /// Reverts the renames recorded in an undo log, most recent first.
+    ///
+    /// Entries whose renamed file no longer exists, or whose original name has been taken since,
+    /// are skipped and reported.
+    fn undo(&self, log: Option<&PathBuf>) -> Result<()> {
+        let (log, entries) = UndoLog::read(log)?;
+        let mut report = Report::new(self.output, self.dry_run);
+        let mut restored = 0;
+        let mut skipped = 0;
+
+        // renames planned when dry running, as (current, restored) paths
+        let mut planned: Vec<(&Path, &Path)> = Vec::new();
+
+        for entry in entries.iter().rev() {
+            // when dry running, parent directories may not have actually been restored yet
+            let current = planned
+                .iter()
+                .find_map(|(current, restored)| {
+                    let rest = entry.to.strip_prefix(restored).ok()?;
+                    (!rest.as_os_str().is_empty()).then(|| current.join(rest))
+                })
+                .unwrap_or_else(|| entry.to.clone());
+
+            let current = match symlink_metadata(&current) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    warn!("File {:?} no longer exists, skipping...", entry.to);
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            // case-only renames point to the same file on case-insensitive filesystems
+            if let Ok(original) = symlink_metadata(&entry.from)
+                && (original.dev(), original.ino()) != (current.dev(), current.ino())
+            {
+                warn!("File {:?} already exists, skipping...", entry.from);
+                skipped += 1;
+                continue;
+            }
+
+            if self.dry_run {
+                planned.push((&entry.to, &entry.from));
+            } else if let Err(err) = self.rename(
+                &entry.to,
+                &entry.from,
+                is_case_only(&entry.to, &entry.from)
+                    && parent_dir(&entry.from)
+                        .is_some_and(|parent| self.list(parent).case_insensitive),
+            ) {
+                error!(
+                    "Failed to rename {:?} to {:?}: {}",
+                    entry.to, entry.from, err
+                );
+                skipped += 1;
+                continue;
+            }
+
+            report.rename(&entry.to, &entry.from, false, &[])?;
+            restored += 1;
+        }
+
+        report.finish()?;
+        info!(
+            "{} {} files from {:?}, {} skipped",
+            if self.dry_run {
+                "Would restore"
+            } else {
+                "Restored"
+            },
+            restored,
+            log,
+            skipped
+        );
+
+        Ok(())
+    }
+
+    /// What to do when the clean name is already taken, `--force` taking precedence over the global `prompt` mode
+    pub(crate) fn conflict(&self, prompt: Prompt) -> Force {
+        self.force.unwrap_or(Force::from_prompt(prompt))
+    }
+
+    /// Maximum depth to recurse into, `None` meaning unlimited.
+    ///
+    /// Without an explicit depth only the contents of the given directories are cleaned,
+    /// unless recursing.
+    fn max_depth(&self) -> Option<usize> {
+        if self.files_from.is_some() {
+            return Some(0);
+        }
+
+        match self.depth {
+            Some(depth) => Some(depth),
+            None if self.recursive => None,
+            None => Some(1),
+        }
+    }
+
+    /// Whether directories found at `depth` should have their contents cleaned.
+    fn should_recurse(&self, depth: usize) -> bool {
+        self.max_depth().is_none_or(|max_depth| depth < max_depth)
+    }
+
+    fn replacement(&self) -> &str {
+        self.replace_with
+            .as_deref()
+            .unwrap_or_else(|| self.style.separator())
+    }
+
+    fn should_lowercase(&self) -> bool {
+        self.lowercase || self.style == Style::Slug
+    }
+
+    /// Why a filename needs cleaning for the current options, or prefixing with the date `prefix`,
+    /// `None` if it's already clean.
+    fn dirt(&self, filename: &str, prefix: Option<&str>) -> Option<&'static str> {
+        if self.normalize_only {
+            return (!is_nfc(filename)).then_some("it isn't in composed form (NFC)");
+        }
+
+        // mapped characters may be allowed ones as well
+        if self
+            .mappings
+            .iter()
+            .any(|(from, _)| filename.contains(from.as_str()))
+        {
+            return Some("it contains characters to substitute");
+        }
+
+        if !filename.chars().all(|c| self.is_allowed(c)) {
+            return Some("it contains characters that aren't allowed");
+        }
+
+        if self.should_lowercase() && filename.chars().any(char::is_uppercase) {
+            return Some("it contains uppercase characters");
+        }
+
+        if filename.len() > self.max_length as usize {
+            return Some("it's longer than the maximum length");
+        }
+
+        if self.windows_safe
+            && (filename.ends_with(['.', ' '])
+                || Self::is_reserved(Self::split_extension(filename).0))
+        {
+            return Some("it isn't valid on Windows");
+        }
+
+        // slugs additionally can't start or end with a separator
+        if self.style == Style::Slug
+            && (filename.starts_with(['_', '-']) || filename.ends_with(['_', '-']))
+        {
+            return Some("it starts or ends with a separator");
+        }
+
+        // names made only of allowed characters may still be cleaned into a tidier one, such as `a__b.txt`
+        let (stem, extension) = Self::split_extension(filename);
+        if std::iter::once(stem)
+            .chain(extension.into_iter().flat_map(|ext| ext.split('.')))
+            .any(|part| !self.is_tidy(part))
+        {
+            return Some("it contains runs of separators, or starts or ends with one");
+        }
+
+        if prefix.is_some_and(|prefix| self.lacks_prefix(filename, prefix)) {
+            return Some("it lacks the date prefix");
+        }
+
+        None
+    }
+
+    /// Whether a part of a filename is left as is by cleaning it, holding no runs of separators and dots,
+    /// and not starting or ending with one, but for the leading dots of hidden files.
+    fn is_tidy(&self, part: &str) -> bool {
+        let name = part.trim_start_matches('.');
+        !self.separator_runs().is_match(name) && self.trim_separators(part) == part
+    }
+
+    /// Runs of separators and dots, such as `__`, `-_-` or `..`
+    fn separator_runs(&self) -> &Regex {
+        self.separator_runs.get_or_init(|| {
+            let separators = regex::escape(&self.separators().iter().collect::<String>());
+            Regex::new(&format!("[{}.]{{2,}}", separators)).unwrap()
+        })
+    }
+
+    /// Whether Windows reserves the given stem as a device name, e.g. `CON` or `com1.tar`.
+    fn is_reserved(stem: &str) -> bool {
+        let name = stem.split('.').next().unwrap_or(stem);
+        WINDOWS_RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    }
+
+    /// Splits a filename into its stem and (possibly multi-part) extension.
+    ///
+    /// Leading dots mark hidden files and are part of the stem, so `.env.local` splits into
+    /// `.env` and `local` while `.gitignore` has no extension at all.
+    fn split_extension(filename: &str) -> (&str, Option<&str>) {
+        let hidden = filename.len() - filename.trim_start_matches('.').len();
+
+        let dot = match filename[hidden..].rfind('.') {
+            // a trailing dot is not an extension separator
+            Some(i) if hidden + i + 1 < filename.len() => hidden + i,
+            _ => return (filename, None),
+        };
+
+        // extend to multi-part extensions such as `.tar.gz`
+        let dot = match filename[hidden..dot].rfind('.') {
+            Some(i)
+                if MULTIPART_EXTENSIONS
+                    .iter()
+                    .any(|ext| ext.eq_ignore_ascii_case(&filename[hidden + i + 1..])) =>
+            {
+                hidden + i
+            }
+            _ => dot,
+        };
+
+        (&filename[..dot], Some(&filename[dot + 1..]))
+    }
+
+    /// Cleans a filename, returning both the clean filename and its clean stem.
+    ///
+    /// Stem and extension are cleaned independently so that the dot separating them
+    /// is never replaced nor collapsed.
+    fn clean_name(&self, filename: &str) -> (String, String) {
+        // decomposed filenames (e.g. from macOS) must give the same result as composed ones,
+        // otherwise combining marks would be replaced on their own
+        let filename: String = filename.nfc().collect();
+
+        if self.normalize_only {
+            let stem = Self::split_extension(&filename).0.to_string();
+            return (filename, stem);
+        }
+
+        // Windows silently drops trailing dots and spaces
+        let filename = if self.windows_safe {
+            filename.trim_end_matches(['.', ' '])
+        } else {
+            &filename
+        };
+
+        let (stem, extension) = Self::split_extension(filename);
+        let mut clean_stem = self.clean_part(stem);
+
+        if self.windows_safe && Self::is_reserved(&clean_stem) {
+            clean_stem.insert(0, '_');
+        }
+
+        let clean_extension: Vec<String> = extension
+            .into_iter()
+            .flat_map(|ext| ext.split('.'))
+            .map(|part| self.clean_part(part))
+            // parts left with no characters to keep are dropped, where a stem falls back to a generated one
+            .filter(|part| !self.is_blank(part))
+            .collect();
+
+        let clean_filename = if clean_extension.is_empty() {
+            clean_stem.clone()
+        } else {
+            format!("{}.{}", clean_stem, clean_extension.join("."))
+        };
+
+        (clean_filename, clean_stem)
+    }
+
+    /// Applies the cleaning pipeline to a single part of a filename.
+    fn clean_part(&self, part: &str) -> String {
+        // substitutions come first, so that they can be written in terms of the original characters
+        let (part, _) = self.apply_map(part);
+
+        // clean the filename by transliterating or removing invalid characters
+        let clean_filename: String = part
+            .graphemes(true)
+            .map(|grapheme| self.clean_grapheme(grapheme))
+            .collect();
+
+        // replace runs of separators and dots, such as `__`, `-_-` or `..`, with their first character,
+        // leaving the leading dots of hidden files alone
+        let separator_runs = self.separator_runs();
+        let hidden = clean_filename.len() - clean_filename.trim_start_matches('.').len();
+        let (dots, name) = clean_filename.split_at(hidden);
+        let clean_filename = format!(
+            "{}{}",
+            dots,
+            separator_runs.replace_all(name, |caps: &regex::Captures| {
+                caps[0].chars().next().unwrap().to_string()
+            })
+        );
+
+        // lowercase after transliteration so that É -> E -> e
+        let clean_filename = if self.should_lowercase() {
+            clean_filename.to_lowercase()
+        } else {
+            clean_filename
+        };
+
+        self.trim_separators(&clean_filename)
+    }
+
+    /// Characters considered to be word separators: underscores, dashes and the replacement character
+    fn separators(&self) -> Vec<char> {
+        let mut separators = vec!['_', '-'];
+        if let Some(c) = self.replacement().chars().next()
+            && !separators.contains(&c)
+        {
+            separators.push(c);
+        }
+        separators
+    }
+
+    /// Trims leading and trailing separators, keeping the leading dots of hidden files.
+    ///
+    /// Parts made only of separators are kept as is, so that they still trigger the blank name safeguard
+    /// instead of silently turning `_.txt` into the hidden file `.txt`.
+    fn trim_separators(&self, part: &str) -> String {
+        let hidden = part.len() - part.trim_start_matches('.').len();
+        let (dots, name) = part.split_at(hidden);
+
+        // trailing dots would otherwise pile up with the one separating the extension
+        let separators = self.separators();
+        let trimmed = name
+            .trim_start_matches(separators.as_slice())
+            .trim_end_matches(|c| c == '.' || separators.contains(&c));
+
+        if trimmed.is_empty() {
+            part.to_string()
+        } else {
+            format!("{}{}", dots, trimmed)
+        }
+    }
+
+    /// Whether a clean stem is empty or made only of separators, e.g. `_`.
+    fn is_blank(&self, stem: &str) -> bool {
+        stem.chars().all(|c| self.separators().contains(&c))
+    }
+
+    /// Looks for a file, other than `path` itself, that `clean_path` would overwrite.
+    ///
+    /// Both files existing on disk and files renamed by this run are considered.
+    /// Names are compared case-insensitively on case-insensitive filesystems, where renaming onto a
+    /// differently cased name would overwrite it, and when lowercasing, so that `Readme.md` and `README.md`
+    /// aren't merged into the same file.
+    fn find_conflict(&self, path: &Path, clean_path: &Path, state: &mut State) -> Option<PathBuf> {
+        let parent = parent_dir(clean_path)?;
+
+        let insensitive = self.is_insensitive(parent, state);
+        let same_path = |a: &Path, b: &Path| {
+            if insensitive {
+                a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+            } else {
+                a == b
+            }
+        };
+
+        if let Some(claimed) = state
+            .claimed
+            .iter()
+            .find(|claimed| same_path(claimed, clean_path))
+        {
+            return Some(claimed.clone());
+        }
+
+        let existing = if !insensitive {
+            // dangling symlinks are conflicts too
+            symlink_metadata(clean_path)
+                .is_ok()
+                .then(|| clean_path.to_path_buf())
+        } else {
+            state.listings[parent]
+                .names
+                .iter()
+                .map(|name| clean_path.with_file_name(name))
+                .find(|existing| existing != path && same_path(existing, clean_path))
+        };
+
+        // files renamed away by this run are no longer a conflict
+        existing.filter(|existing| !state.vacated.contains(existing))
+    }
+
+    /// Separator used when appending to a stem, the style separator if characters are being removed
+    fn separator(&self) -> &str {
+        match self.replacement() {
+            "" => self.style.separator(),
+            replacement => replacement,
+        }
+    }
+
+    /// Truncates the stem of a filename longer than `--max-length` on a character boundary,
+    /// keeping the whole extension and appending a short hash of the original filename
+    /// so that long names sharing a prefix don't collide.
+    ///
+    /// Returns the resulting filename and whether it was truncated.
+    fn truncate(&self, clean_filename: String, filename: &str) -> (String, bool) {
+        let max_length = self.max_length as usize;
+        if clean_filename.len() <= max_length {
+            return (clean_filename, false);
+        }
+
+        let (stem, extension) = Self::split_extension(&clean_filename);
+        let extension = extension.map(|ext| format!(".{}", ext)).unwrap_or_default();
+        let hash = format!("{}{}", self.separator(), short_hash(filename));
+
+        // overly long extensions are truncated along with the stem
+        let (stem, extension) = match max_length.checked_sub(extension.len() + hash.len()) {
+            Some(length) => (
+                &stem[..floor_char_boundary(stem, length)],
+                extension.as_str(),
+            ),
+            None => {
+                let length = max_length - hash.len();
+                (
+                    &clean_filename[..floor_char_boundary(&clean_filename, length)],
+                    "",
+                )
+            }
+        };
+
+        (format!("{}{}{}", stem, hash, extension), true)
+    }
+
+    /// Whether names in `dir` should be compared case-insensitively
+    fn is_insensitive(&self, dir: &Path, state: &mut State) -> bool {
+        let listing = state
+            .listings
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| self.list(dir));
+
+        // lowercased names are meant to be compared without case regardless of the filesystem
+        listing.case_insensitive || self.should_lowercase()
+    }
+
+    /// Lists the contents of `dir`, detecting whether it's case-insensitive unless told to assume it.
+    fn list(&self, dir: &Path) -> Listing {
+        let names: Vec<OsString> = match dir.read_dir() {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Listing {
+            case_insensitive: self.case_insensitive || Self::is_case_insensitive(dir, &names),
+            names,
+        }
+    }
+
+    /// Detects whether `dir` is case-insensitive without writing to it, by looking up an entry,
+    /// or the directory itself, under a differently cased name and checking whether it's the same file.
+    ///
+    /// Case sensitivity is detected per directory, as some filesystems such as ext4 allow setting it as such.
+    fn is_case_insensitive(dir: &Path, names: &[OsString]) -> bool {
+        let swap_case = |name: &str| -> String {
+            name.chars()
+                .flat_map(|c| {
+                    if c.is_uppercase() {
+                        c.to_lowercase().collect::<Vec<_>>()
+                    } else {
+                        c.to_uppercase().collect()
+                    }
+                })
+                .collect()
+        };
+
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let candidates = names
+            .iter()
+            .map(|name| dir.join(name))
+            .chain(dir.parent().is_some().then(|| dir.clone()));
+
+        for candidate in candidates {
+            let Some(name) = candidate.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            let swapped = swap_case(name);
+            if swapped == name {
+                continue;
+            }
+
+            return match (
+                symlink_metadata(&candidate),
+                symlink_metadata(candidate.with_file_name(swapped)),
+            ) {
+                (Ok(original), Ok(swapped)) => {
+                    (original.dev(), original.ino()) == (swapped.dev(), swapped.ino())
+                }
+                _ => false,
+            };
+        }
+
+        false
+    }
+
+    /// Appends the first free numeric suffix to the stem of `clean_filename`,
+    /// i.e. `foo_bar.txt` becomes `foo_bar_1.txt`.
+    fn suffixed_path(&self, path: &Path, clean_filename: &str, state: &mut State) -> PathBuf {
+        let (stem, extension) = Self::split_extension(clean_filename);
+        let separator = self.separator();
+
+        (1..)
+            .map(|i| match extension {
+                Some(extension) => format!("{}{}{}.{}", stem, separator, i, extension),
+                None => format!("{}{}{}", stem, separator, i),
+            })
+            .map(|filename| path.with_file_name(filename))
+            .find(|candidate| self.find_conflict(path, candidate, state).is_none())
+            .unwrap()
+    }
+
+    /// Renames `path` to `clean_path`.
+    ///
+    /// Case-only renames on case-insensitive filesystems go through an intermediate name,
+    /// as they would otherwise consider both names to be the same file and do nothing.
+    /// Should the second step fail, the file is moved back to its original name.
+    fn rename(&self, path: &Path, clean_path: &Path, case_insensitive: bool) -> io::Result<()> {
+        if !case_insensitive || !is_case_only(path, clean_path) {
+            return rename(path, clean_path);
+        }
+
+        let clean_filename = clean_path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = (0..)
+            .map(|i| {
+                clean_path.with_file_name(format!(
+                    ".{}.{}-{}.tmp",
+                    clean_filename,
+                    process::id(),
+                    i
+                ))
+            })
+            .find(|tmp_path| symlink_metadata(tmp_path).is_err())
+            .unwrap();
+
+        rename(path, &tmp_path)?;
+        if let Err(err) = rename(&tmp_path, clean_path) {
+            if let Err(restore_err) = rename(&tmp_path, path) {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!(
+                        "{}, and restoring from {:?} failed: {}",
+                        err, tmp_path, restore_err
+                    ),
+                ));
+            }
+
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Characters allowed in clean filenames: simple ascii, no whitespaces, plus any `--allow`ed ones.
+    fn is_allowed(&self, c: char) -> bool {
+        if self.windows_safe && (c.is_ascii_control() || WINDOWS_INVALID.contains(&c)) {
+            return false;
+        }
+
+        c.is_ascii_alphanumeric()
+            || ['_', '-', '.'].contains(&c)
+            || self
+                .allow
+                .as_ref()
+                .is_some_and(|allow| allow.is_match(c.encode_utf8(&mut [0; 4])))
+    }
+
+    /// Cleans a single grapheme cluster, i.e. what is visually a single character such as `🇺🇸` or `👍🏽`,
+    /// so that it becomes at most a single replacement character.
+    ///
+    /// Allowed characters leading the cluster are kept as is, while combining marks, joiners
+    /// and modifiers following them, such as skin tones, are dropped. Symbols such as emoji are transliterated
+    /// to words set apart by the replacement character, `photo👨‍👩‍👧` giving `photo_man_woman_girl_`.
+    fn clean_grapheme(&self, grapheme: &str) -> String {
+        let split = grapheme
+            .char_indices()
+            .find(|(_, c)| !self.is_allowed(*c))
+            .map_or(grapheme.len(), |(i, _)| i);
+        let (kept, rest) = grapheme.split_at(split);
+
+        if rest.chars().all(is_modifier) {
+            return kept.to_string();
+        }
+
+        if !self.no_transliterate {
+            // e.g. é -> e, ß -> ss, 中 -> "Zhong ", 🇺🇸 -> US, 👨‍👩‍👧 -> "man woman girl"
+            let ascii = rest
+                .split('\u{200d}')
+                .map(|joined| {
+                    joined
+                        .chars()
+                        .filter(|c| !is_modifier(*c))
+                        .filter_map(|c| {
+                            if self.is_allowed(c) {
+                                Some(c.to_string())
+                            } else {
+                                deunicode_char(c).map(str::to_string)
+                            }
+                        })
+                        .collect::<String>()
+                })
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            // the transliteration itself may contain invalid characters such as whitespaces
+            if ascii.chars().any(|c| c.is_ascii_alphanumeric()) {
+                let ascii: String = ascii
+                    .chars()
+                    .map(|c| {
+                        if self.is_allowed(c) {
+                            c.to_string()
+                        } else {
+                            self.replacement().to_string()
+                        }
+                    })
+                    .collect();
+
+                // words of symbols would otherwise run into the surrounding ones
+                if rest.chars().any(char::is_alphabetic) {
+                    return format!("{}{}", kept, ascii);
+                }
+                let replacement = self.replacement();
+                return format!("{}{}{}{}", kept, replacement, ascii, replacement);
+            }
+        }
+
+        // no mapping available
+        format!("{}{}", kept, self.replacement())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        cln: Cln,
+    }
+
+    /// Cln with the given arguments, as given on the command line
+    fn cln(args: &[&str]) -> Cln {
+        Cli::parse_from(["cln"].iter().chain(args)).cln
+    }
+
+    /// Name `filename` is renamed to, as `clean_file` decides it, the same name if it's already clean
+    fn clean(cln: &Cln, filename: &str) -> String {
+        match cln.dirt(filename, None) {
+            Some(_) => cln.propose(filename, None).0,
+            None => filename.to_string(),
+        }
+    }
+
+    /// Checks that every filename is renamed to the expected one, which is left as is when cleaned again
+    fn assert_cleans(cln: &Cln, cases: &[(&str, &str)]) {
+        for (filename, expected) in cases {
+            let cleaned = clean(cln, filename);
+            assert_eq!(&cleaned, expected, "cleaning {:?}", filename);
+            assert_eq!(
+                clean(cln, &cleaned),
+                cleaned,
+                "cleaning {:?} again",
+                cleaned
+            );
+        }
+    }
+
+    #[test]
+    fn collapses_runs_of_allowed_characters() {
+        assert_cleans(
+            &cln(&[]),
+            &[
+                ("file..txt", "file.txt"),
+                ("a__b.txt", "a_b.txt"),
+                ("a._-b.txt", "a.b.txt"),
+                ("a-_-b", "a-b"),
+                ("a.tar..gz", "a.tar.gz"),
+            ],
+        );
+    }
+
+    #[test]
+    fn cleaning_is_idempotent() {
+        let names = [
+            "file..txt",
+            "a__b.txt",
+            "a._-b.txt",
+            "__a__.txt",
+            "My File (1).txt",
+            "file - - copy..txt",
+            ".bashrc",
+            ".env.local",
+            "archive.tar.gz",
+            "Straße.txt",
+            "a.",
+            "_.txt",
+            "★.tar.gz",
+            "trailing dots...",
+        ];
+        for args in [
+            &[][..],
+            &["-s", "dash"],
+            &["-s", "slug"],
+            &["-w"],
+            &["-k", ""],
+        ] {
+            let cln = cln(args);
+            for name in names {
+                let cleaned = clean(&cln, name);
+                assert_eq!(
+                    clean(&cln, &cleaned),
+                    cleaned,
+                    "cleaning {:?} twice with {:?}",
+                    name,
+                    args
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trims_and_collapses_mixed_separators() {
+        assert_cleans(
+            &cln(&[]),
+            &[
+                ("__a__.txt", "a.txt"),
+                ("_-_my_file_-.txt", "my_file.txt"),
+                ("-a-", "a"),
+                ("a_-_b.txt", "a_b.txt"),
+                ("my file_.txt", "my_file.txt"),
+                ("_a.txt_", "a.txt"),
+                (".__hidden", ".hidden"),
+            ],
+        );
+    }
+
+    #[test]
+    fn dash_style_trims_and_collapses_separators() {
+        assert_cleans(
+            &cln(&["-s", "dash"]),
+            &[
+                ("--a--.txt", "a.txt"),
+                ("My File.txt", "My-File.txt"),
+                ("-_-a-_-b-_-.txt", "a-b.txt"),
+                ("a_b.txt", "a_b.txt"),
+                ("a  b", "a-b"),
+            ],
+        );
+    }
+
+    #[test]
+    fn slug_style_trims_and_collapses_separators() {
+        assert_cleans(
+            &cln(&["-s", "slug"]),
+            &[
+                ("--A--B--.txt", "a-b.txt"),
+                ("My File.TXT", "my-file.txt"),
+                ("_-_My_File_-.txt", "my_file.txt"),
+                ("-a-", "a"),
+                ("a-.txt", "a.txt"),
+            ],
+        );
+    }
+
+    #[test]
+    fn sets_transliterated_symbols_apart() {
+        assert_cleans(
+            &cln(&[]),
+            &[
+                (
+                    "photo\u{200d}👨\u{200d}👩\u{200d}👧.jpg",
+                    "photo_man_woman_girl.jpg",
+                ),
+                ("photo👨‍👩‍👧.jpg", "photo_man_woman_girl.jpg"),
+                ("trip🇺🇸.txt", "trip_US.txt"),
+                ("🇺🇸.txt", "US.txt"),
+                ("a♥b.txt", "a_hearts_b.txt"),
+            ],
+        );
+        assert_cleans(
+            &cln(&["-s", "dash"]),
+            &[("photo👨‍👩‍👧.jpg", "photo-man-woman-girl.jpg")],
+        );
+    }
+
+    #[test]
+    fn drops_skin_tones() {
+        let cln = cln(&[]);
+        assert_eq!(clean(&cln, "👍🏽.txt"), clean(&cln, "👍.txt"));
+        assert_eq!(clean(&cln, "ok👋🏿.txt"), clean(&cln, "ok👋.txt"));
+        assert_cleans(&cln, &[("a\u{1f3fd}b.txt", "ab.txt")]);
+    }
+
+    #[test]
+    fn cleans_combining_marks_with_their_character() {
+        assert_cleans(
+            &cln(&[]),
+            &[
+                ("cafe\u{301}.txt", "cafe.txt"),
+                ("n\u{303}u\u{308}.txt", "nu.txt"),
+                ("a\u{20dd}.txt", "a.txt"),
+                ("x\u{fe0f}.txt", "x.txt"),
+            ],
+        );
+        assert_cleans(
+            &cln(&["--no-transliterate"]),
+            &[
+                ("cafe\u{301}.txt", "caf.txt"),
+                ("cafe\u{301}s.txt", "caf_s.txt"),
+                ("trip🇺🇸.txt", "trip.txt"),
+            ],
+        );
+    }
+
+    #[test]
+    fn cleans_the_current_directory_by_default() {
+        assert_eq!(cln(&[]).paths, [Path::new(".")]);
+        assert_eq!(cln(&["a", "b"]).paths, [Path::new("a"), Path::new("b")]);
+        assert!(Cli::try_parse_from(["cln", "--files-from", "-"]).is_ok());
+        assert!(Cli::try_parse_from(["cln", "a", "--files-from", "-"]).is_err());
+    }
+
+    #[test]
+    fn limits_the_recursion_depth() {
+        // the given paths are at depth 0, their entries at depth 1, and so on
+        for (args, max_depth, recursed) in [
+            (&[][..], Some(1), [true, false, false, false]),
+            (&["--depth", "0"], Some(0), [false, false, false, false]),
+            (&["--depth", "1"], Some(1), [true, false, false, false]),
+            (&["--depth", "3"], Some(3), [true, true, true, false]),
+            (&["--recursive"], None, [true, true, true, true]),
+            (
+                &["--recursive", "--depth", "0"],
+                Some(0),
+                [false, false, false, false],
+            ),
+            (
+                &["--recursive", "--depth", "2"],
+                Some(2),
+                [true, true, false, false],
+            ),
+            (
+                &["--files-from", "-"],
+                Some(0),
+                [false, false, false, false],
+            ),
+        ] {
+            let cln = cln(args);
+            assert_eq!(cln.max_depth(), max_depth, "with {args:?}");
+            assert_eq!(
+                (0..4)
+                    .map(|depth| cln.should_recurse(depth))
+                    .collect::<Vec<_>>(),
+                recursed,
+                "with {args:?}"
+            );
+        }
+        assert!(cln(&["--recursive"]).should_recurse(usize::MAX));
+    }
+
+    #[test]
+    fn sets_windows_reserved_names_apart() {
+        let cln = cln(&["--windows-safe"]);
+        let reserved = ["CON", "PRN", "AUX", "NUL"]
+            .map(String::from)
+            .into_iter()
+            .chain((1..=9).flat_map(|i| [format!("COM{i}"), format!("LPT{i}")]));
+        for name in reserved {
+            let lowercase = name.to_lowercase();
+            let title = format!("{}{}", &name[..1], &lowercase[1..]);
+            for name in [name.clone(), lowercase, title] {
+                assert!(Cln::is_reserved(&name), "{name:?} is reserved");
+                assert!(cln.dirt(&name, None).is_some(), "{name:?} is flagged");
+                assert_cleans(
+                    &cln,
+                    &[
+                        (&name, &format!("_{name}")),
+                        (&format!("{name}.txt"), &format!("_{name}.txt")),
+                        (&format!("{name}.tar.gz"), &format!("_{name}.tar.gz")),
+                    ],
+                );
+            }
+        }
+        assert_cleans(
+            &cln,
+            &[
+                ("report.", "report"),
+                ("notes ", "notes"),
+                ("a<b>c.txt", "a_b_c.txt"),
+            ],
+        );
+    }
+
+    #[test]
+    fn leaves_lookalikes_of_reserved_names_alone() {
+        let cln = cln(&["--windows-safe"]);
+        for name in [
+            "console",
+            "com10",
+            "COM0",
+            "lpt",
+            "LPT10",
+            "auxiliary",
+            "prn2",
+            "acon",
+            "COM",
+            "connect.txt",
+            "my.con",
+        ] {
+            assert!(
+                !Cln::is_reserved(Cln::split_extension(name).0),
+                "{name:?} isn't reserved"
+            );
+            assert_eq!(clean(&cln, name), name);
+        }
+        // reserved names are only cleaned with --windows-safe
+        assert_eq!(clean(&self::cln(&[]), "CON.txt"), "CON.txt");
+    }
+
+    #[test]
+    fn transliterates_composed_and_decomposed_names_alike() {
+        assert_cleans(
+            &cln(&[]),
+            &[
+                ("café.txt", "cafe.txt"),
+                ("cafe\u{301}.txt", "cafe.txt"),
+                ("résumé.pdf", "resume.pdf"),
+                ("re\u{301}sume\u{301}.pdf", "resume.pdf"),
+                ("Straße.txt", "Strasse.txt"),
+                ("Füße.txt", "Fusse.txt"),
+                ("Fu\u{308}ße.txt", "Fusse.txt"),
+                ("Ångström.md", "Angstrom.md"),
+                ("A\u{30a}ngstro\u{308}m.md", "Angstrom.md"),
+                ("中文.txt", "Zhong_Wen.txt"),
+            ],
+        );
+        assert_eq!(
+            clean(&cln(&[]), "cafe\u{301}.txt"),
+            clean(&cln(&[]), "café.txt")
+        );
+        // without transliterating, decomposed characters are replaced along with their marks
+        assert_cleans(
+            &cln(&["--no-transliterate"]),
+            &[
+                ("résumé.pdf", "r_sum.pdf"),
+                ("re\u{301}sume\u{301}.pdf", "r_sum.pdf"),
+                ("Straße.txt", "Stra_e.txt"),
+            ],
+        );
+    }
+
+    #[test]
+    fn splits_extensions() {
+        for (filename, stem, extension) in [
+            ("file.txt", "file", Some("txt")),
+            ("file", "file", None),
+            ("a.b.c", "a.b", Some("c")),
+            ("a.tar.gz", "a", Some("tar.gz")),
+            ("backup.2024.tar.zst", "backup.2024", Some("tar.zst")),
+            ("a.TAR.GZ", "a", Some("TAR.GZ")),
+            (".bashrc", ".bashrc", None),
+            ("..hidden", "..hidden", None),
+            (".env.local", ".env", Some("local")),
+            (".config.tar.gz", ".config", Some("tar.gz")),
+            ("file.", "file.", None),
+            (".", ".", None),
+        ] {
+            assert_eq!(
+                Cln::split_extension(filename),
+                (stem, extension),
+                "splitting {filename:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cleans_stems_and_extensions_apart() {
+        let cln = cln(&[]);
+        assert_cleans(
+            &cln,
+            &[
+                (".bashrc", ".bashrc"),
+                (".env.local", ".env.local"),
+                (".my env.local", ".my_env.local"),
+                ("a.tar.gz", "a.tar.gz"),
+                ("my file.tar.gz", "my_file.tar.gz"),
+                ("données.résumé", "donnees.resume"),
+                ("v1.2 notes.txt", "v1.2_notes.txt"),
+                ("file..txt", "file.txt"),
+                ("report.", "report"),
+                (".txt", ".txt"),
+                (".résumé", ".resume"),
+                ("a.???", "a"),
+            ],
+        );
+
+        // only the stem is checked for being empty, falling back to a generated one
+        let cleaned = clean(&cln, "???.txt");
+        assert!(
+            cleaned.starts_with("unnamed-") && cleaned.ends_with(".txt"),
+            "{cleaned:?}"
+        );
+        assert_eq!(clean(&cln, &cleaned), cleaned);
+    }
+
+    /// Cleans the tree at `dir` as `run` does, without an undo log, giving the renames reported in order
+    /// relative to `dir`
+    fn clean_tree(cln: &Cln, dir: &Path) -> Vec<(String, String)> {
+        let mut state = State {
+            report: Report::new(Output::Json, cln.dry_run),
+            ..Default::default()
+        };
+        let node = cln.scan(dir, dir, 0, &Mutex::default());
+        cln.plan(std::iter::once((dir, &node)), &mut state).unwrap();
+        cln.clean_files(dir, vec![node], &mut state).unwrap();
+
+        let relative = |path: String| {
+            let path = Path::new(&path).strip_prefix(dir).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        state
+            .report
+            .renames()
+            .into_iter()
+            .map(|(from, to)| (relative(from), relative(to)))
+            .collect()
+    }
+
+    /// Creates a messy tree of nested directories in `dir`
+    fn messy_tree(dir: &Path) {
+        std::fs::create_dir_all(dir.join("My Dir/Sub Dir")).unwrap();
+        std::fs::write(dir.join("My Dir/Some File.txt"), "").unwrap();
+        std::fs::write(dir.join("My Dir/Sub Dir/Deep File.txt"), "").unwrap();
+        std::fs::write(dir.join("clean.txt"), "").unwrap();
+    }
+
+    /// Paths found in `dir`, relative to it and sorted, without following symlinks
+    fn tree(dir: &Path) -> Vec<String> {
+        let mut paths = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(next) = dirs.pop() {
+            for entry in next.read_dir().unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() && !path.is_symlink() {
+                    dirs.push(path.clone());
+                }
+                let relative = path.strip_prefix(dir).unwrap();
+                paths.push(relative.to_str().unwrap().to_string());
+            }
+        }
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn renames_directories_after_their_contents() {
+        let dir = tempfile::TempDir::new().unwrap();
+        messy_tree(dir.path());
+
+        let renames = clean_tree(&cln(&["--recursive"]), dir.path());
+        assert_eq!(
+            tree(dir.path()),
+            [
+                "My_Dir",
+                "My_Dir/Some_File.txt",
+                "My_Dir/Sub_Dir",
+                "My_Dir/Sub_Dir/Deep_File.txt",
+                "clean.txt",
+            ]
+        );
+
+        let mut sorted = renames.clone();
+        sorted.sort();
+        assert_eq!(
+            sorted,
+            [
+                ("My Dir", "My_Dir"),
+                ("My Dir/Some File.txt", "My Dir/Some_File.txt"),
+                ("My Dir/Sub Dir", "My Dir/Sub_Dir"),
+                (
+                    "My Dir/Sub Dir/Deep File.txt",
+                    "My Dir/Sub Dir/Deep_File.txt"
+                ),
+            ]
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+        );
+        // contents are renamed before their directory, through its original name
+        for (i, (from, _)) in renames.iter().enumerate() {
+            for (inner, _) in &renames[i + 1..] {
+                assert!(
+                    !Path::new(inner).starts_with(from),
+                    "{inner:?} renamed after {from:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn follows_symlink_loops_once() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("Some File.txt"), "").unwrap();
+        std::os::unix::fs::symlink(".", dir.path().join("self")).unwrap();
+
+        let renames = clean_tree(&cln(&["--recursive", "--follow-symlinks"]), dir.path());
+        assert_eq!(
+            renames,
+            [("Some File.txt".to_string(), "Some_File.txt".to_string())]
+        );
+        assert_eq!(tree(dir.path()), ["Some_File.txt", "self"]);
+    }
+
+    #[test]
+    fn renames_symlinks_rather_than_their_targets() {
+        let (dir, outside) = (
+            tempfile::TempDir::new().unwrap(),
+            tempfile::TempDir::new().unwrap(),
+        );
+        std::fs::write(outside.path().join("Outside File.txt"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("My Link")).unwrap();
+
+        let renames = clean_tree(&cln(&["--recursive"]), dir.path());
+        assert_eq!(renames, [("My Link".to_string(), "My_Link".to_string())]);
+        assert_eq!(
+            std::fs::read_link(dir.path().join("My_Link")).unwrap(),
+            outside.path()
+        );
+        // the target is left alone, and never descended into
+        assert_eq!(tree(outside.path()), ["Outside File.txt"]);
+    }
+
+    #[test]
+    fn plans_the_renames_it_performs() {
+        let (dir, dry_dir) = (
+            tempfile::TempDir::new().unwrap(),
+            tempfile::TempDir::new().unwrap(),
+        );
+        messy_tree(dir.path());
+        messy_tree(dry_dir.path());
+        let before = tree(dry_dir.path());
+
+        let mut cln = cln(&["--recursive"]);
+        cln.dry_run = true;
+        let mut planned = clean_tree(&cln, dry_dir.path());
+        assert_eq!(tree(dry_dir.path()), before);
+
+        cln.dry_run = false;
+        let mut renamed = clean_tree(&cln, dir.path());
+        planned.sort();
+        renamed.sort();
+        assert_eq!(planned, renamed);
+        assert!(clean_tree(&cln, dir.path()).is_empty());
+    }
+
+    #[test]
+    fn exits_distinctly_when_everything_is_already_clean() {
+        let dir = tempfile::TempDir::new().unwrap();
+        messy_tree(dir.path());
+        let ctx = Context {
+            dry_run: true,
+            quiet: true,
+            color: crate::cli::Color::Off,
+            prompt: Prompt::No,
+            config: Default::default(),
+        };
+        let run = |args: &[&str]| {
+            let path = dir.path().to_str().unwrap();
+            cln(&[&["--recursive", path], args].concat())
+                .run(&ctx)
+                .unwrap()
+        };
+
+        assert_eq!(run(&[]), ExitCode::SUCCESS);
+        assert_eq!(run(&["--check"]), ExitCode::FAILURE);
+
+        clean_tree(&cln(&["--recursive"]), dir.path());
+        assert_eq!(run(&[]), ExitCode::from(EXIT_ALREADY_CLEAN));
+        // checks keep exiting with success on clean trees
+        assert_eq!(run(&["--check"]), ExitCode::SUCCESS);
+    }
+}
@@ -0,0 +1,121 @@
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{Write, stdout},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+use tracing::info;
+
+/// Format used to report renames
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    /// Human readable `"old" -> "new"` lines, written to stderr along with other messages
+    #[default]
+    Text,
+    /// A JSON array of `{from, to, conflict}` objects, printed once done
+    Json,
+    /// NUL delimited `from\0to\0` pairs, with paths written as raw bytes
+    Nul,
+}
+
+/// A single reported rename
+///
+/// JSON can't hold arbitrary bytes, so paths that aren't valid UTF-8 are converted lossily
+/// and flagged as such.
+#[derive(Debug, Serialize)]
+struct Entry {
+    from: String,
+    to: String,
+    conflict: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    mapped: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    lossy: bool,
+}
+
+/// Reports renames as they are performed, or planned when dry running, in the chosen output format.
+#[derive(Debug, Default)]
+pub struct Report {
+    output: Output,
+    dry_run: bool,
+    entries: Vec<Entry>,
+}
+
+impl Report {
+    pub fn new(output: Output, dry_run: bool) -> Self {
+        Self {
+            output,
+            dry_run,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Reports a rename from `from` to `to`, `conflict` telling whether the clean name was already taken
+    /// and `mapped` listing the `--map` substitutions involved.
+    pub fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        conflict: bool,
+        mapped: &[&(String, String)],
+    ) -> Result<()> {
+        match self.output {
+            Output::Text => {
+                let mut line = format!("{:?} -> {:?}", from, to);
+                if self.dry_run {
+                    line.insert_str(0, "Would rename ");
+                }
+                if !mapped.is_empty() {
+                    let mapped: Vec<String> = mapped
+                        .iter()
+                        .map(|(from, to)| format!("{:?} -> {:?}", from, to))
+                        .collect();
+                    line.push_str(&format!(" (mapped {})", mapped.join(", ")));
+                }
+                info!("{}", line);
+            }
+            Output::Json => self.entries.push(Entry {
+                from: from.to_string_lossy().into_owned(),
+                to: to.to_string_lossy().into_owned(),
+                conflict,
+                mapped: mapped
+                    .iter()
+                    .map(|&(from, to)| (from.clone(), to.clone()))
+                    .collect(),
+                lossy: from.to_str().is_none() || to.to_str().is_none(),
+            }),
+            Output::Nul => {
+                let mut out = stdout().lock();
+                out.write_all(from.as_os_str().as_bytes())?;
+                out.write_all(b"\0")?;
+                out.write_all(to.as_os_str().as_bytes())?;
+                out.write_all(b"\0")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renames reported so far in JSON, as `from` and `to` paths
+    #[cfg(test)]
+    pub fn renames(&self) -> Vec<(String, String)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.from.clone(), entry.to.clone()))
+            .collect()
+    }
+
+    /// Writes out the reported renames, if the output format requires all of them at once
+    pub fn finish(self) -> Result<()> {
+        match self.output {
+            Output::Json => println!("{}", serde_json::to_string_pretty(&self.entries)?),
+            Output::Text => {}
+            Output::Nul => stdout().flush()?,
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::Local;
+use color_eyre::eyre::{Result, bail, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{File, OpenOptions, create_dir_all, read_dir, read_to_string, remove_file},
+    io::Write,
+    path::{Path, PathBuf, absolute},
+    process,
+};
+use tracing::warn;
+
+/// A single rename performed by cln
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub timestamp: String,
+}
+
+/// Log of the renames performed during a run, stored as one JSON entry per line
+/// under `$XDG_STATE_HOME/dsu/cln`.
+///
+/// Entries are appended as soon as each rename is done,
+/// so that an interrupted run still leaves a usable log behind.
+#[derive(Debug)]
+pub struct UndoLog {
+    path: PathBuf,
+    file: File,
+    entries: usize,
+}
+
+impl UndoLog {
+    pub fn create() -> Result<Self> {
+        let dir = log_dir().ok_or_else(|| eyre!("Unable to determine the undo log directory"))?;
+        if let Err(err) = create_dir_all(&dir) {
+            bail!("Failed to create undo log directory {:?}: {}", dir, err);
+        }
+
+        let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let path = dir.join(format!("{}-{}.jsonl", timestamp, process::id()));
+
+        match OpenOptions::new().create_new(true).append(true).open(&path) {
+            Ok(file) => Ok(Self {
+                path,
+                file,
+                entries: 0,
+            }),
+            Err(err) => bail!("Failed to create undo log {:?}: {}", path, err),
+        }
+    }
+
+    /// Appends a rename to the log
+    pub fn record(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let entry = Entry {
+            from: absolute(from)?,
+            to: absolute(to)?,
+            timestamp: Local::now().to_rfc3339(),
+        };
+
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.entries += 1;
+
+        Ok(())
+    }
+
+    /// Closes the log, returning its path unless nothing was recorded, in which case it's removed
+    pub fn finish(self) -> Option<PathBuf> {
+        if self.entries > 0 {
+            return Some(self.path);
+        }
+
+        drop(self.file);
+        let _ = remove_file(&self.path);
+        None
+    }
+
+    /// Reads the entries of the given log, or of the most recent one if none is given
+    pub fn read(path: Option<&PathBuf>) -> Result<(PathBuf, Vec<Entry>)> {
+        let path = match path {
+            Some(path) => path.clone(),
+            None => latest_log()?,
+        };
+
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => bail!("Failed to read undo log {:?}: {}", path, err),
+        };
+
+        let lines: Vec<&str> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        let mut entries = Vec::with_capacity(lines.len());
+
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                // the last entry may have been cut short by a crash
+                Err(_) if i == lines.len() - 1 => {
+                    warn!("Ignoring incomplete last entry of undo log {:?}", path);
+                }
+                Err(err) => bail!("Invalid entry in undo log {:?}: {}", path, err),
+            }
+        }
+
+        Ok((path, entries))
+    }
+}
+
+/// `$XDG_STATE_HOME/dsu/cln`, falling back to `~/.local/state/dsu/cln`
+fn log_dir() -> Option<PathBuf> {
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+
+    Some(state_dir.join("dsu").join("cln"))
+}
+
+fn latest_log() -> Result<PathBuf> {
+    let dir = log_dir().ok_or_else(|| eyre!("Unable to determine the undo log directory"))?;
+
+    // log names start with a timestamp, so the greatest one is the most recent
+    read_dir(&dir)
+        .ok()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .max()
+        .ok_or_else(|| eyre!("No undo log found in {:?}", dir))
+}
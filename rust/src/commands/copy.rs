@@ -1,21 +1,677 @@
-use crate::cli::Runnable;
+use crate::cli::{Context, Runnable};
+use crate::utils::clipboard::{self, Backend, Clipboard, Contents, LineEnding, Serving, System};
+use crate::utils::size::{human_size, parse_size};
+use base64::{Engine, engine::general_purpose::STANDARD};
 use clap::Args;
-use color_eyre::eyre::{bail, eyre, Result};
-use copypasta::{ClipboardContext, ClipboardProvider};
-use std::io::{stdin, Read};
+use color_eyre::eyre::{Result, bail};
+use std::fs::File;
+use std::io::{ErrorKind, IsTerminal, Read, Write, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::{error, warn};
 
 #[derive(Args, Debug)]
-pub struct Copy {}
+pub struct Copy {
+    /// Files whose contents are copied, concatenated if several, instead of reading from stdin
+    pub files: Vec<PathBuf>,
+
+    /// Text inserted between the contents of several files [default: newline]
+    #[arg(long, default_value = "\n", hide_default_value = true)]
+    pub separator: String,
+
+    /// Copy the image read from the file, or from stdin, instead of text, either PNG or JPEG
+    #[arg(long, conflicts_with_all = ["separator", "trim", "trim_all", "strip_ansi", "keep_ansi"])]
+    pub image: bool,
+
+    /// Copy the text as HTML, along with a plain text version for applications that don't take rich text
+    #[arg(long, conflicts_with_all = ["image", "base64", "markdown"])]
+    pub html: bool,
+
+    /// Copy the text rendered from Markdown to HTML, along with the Markdown itself as plain text
+    #[arg(long, conflicts_with_all = ["image", "base64"])]
+    pub markdown: bool,
+
+    /// Plain text copied along with `--html` or `--markdown`, instead of the text of the HTML
+    #[arg(long, value_name = "TEXT")]
+    pub text_fallback: Option<String>,
+
+    /// Remove ANSI escape sequences, such as colors, done by default for text piped to stdin
+    #[arg(long, conflicts_with = "keep_ansi")]
+    pub strip_ansi: bool,
+
+    /// Keep ANSI escape sequences in text piped to stdin
+    #[arg(long)]
+    pub keep_ansi: bool,
+
+    /// Copy the contents read from the file, or from stdin, encoded as base64, so that binary ones are kept intact
+    #[arg(long, conflicts_with_all = ["separator", "image", "trim", "trim_all", "strip_ansi", "keep_ansi"])]
+    pub base64: bool,
+
+    /// Pass stdin through to stdout as it's read, copying it once it's over, as `tee` does to files
+    #[arg(long, conflicts_with_all = ["files", "image", "base64"])]
+    pub tee: bool,
+
+    /// Refuse input larger than the size, such as `64MiB`, which clipboards would mostly fail to hold anyway
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "16MiB")]
+    pub max_size: u64,
+
+    /// Remove a single trailing newline, such as the one ending the output of most commands
+    #[arg(long, short = 't', conflicts_with = "trim_all")]
+    pub trim: bool,
+
+    /// Remove all leading and trailing whitespace
+    #[arg(long)]
+    pub trim_all: bool,
+
+    /// Convert line endings to CRLF, as Windows uses
+    #[arg(long, conflicts_with_all = ["lf", "image", "base64"])]
+    pub crlf: bool,
+
+    /// Convert line endings to LF, as Unix systems use
+    #[arg(long, conflicts_with_all = ["image", "base64"])]
+    pub lf: bool,
+
+    /// Clipboard to copy to, OSC 52 copying through the terminal
+    #[arg(long, value_enum, default_value_t = Backend::Auto)]
+    pub backend: Backend,
+
+    /// Give up on a backend not responding within the duration, such as an X server that stopped, trying the next one
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "5s")]
+    pub timeout: Duration,
+
+    /// Serve the copied contents until other contents are copied, instead of from a background process,
+    /// on X11 and Wayland where the process copying them has to
+    #[arg(long)]
+    pub foreground: bool,
+
+    /// Clear the copied contents after the duration, such as `30s`, unless other contents were copied meanwhile
+    #[arg(long, alias = "keep-for", value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub expire: Option<Duration>,
+
+    /// Empty the clipboard instead of copying
+    #[arg(long, conflicts_with_all = ["files", "image", "html", "markdown", "base64", "tee", "expire"])]
+    pub clear: bool,
+
+    /// Serve the contents read from stdin as the background process started by copy itself
+    #[arg(long, hide = true)]
+    pub serve: bool,
+}
 
 impl Runnable for Copy {
-    fn run(&mut self) -> Result<()> {
-        let mut input = String::new();
-        if let Err(err) = stdin().read_to_string(&mut input) {
-            bail!("Failed to read from stdin: {}", err);
+    fn run(&mut self, _ctx: &Context) -> Result<ExitCode> {
+        let serving = Serving {
+            foreground: self.foreground,
+            expire: self.expire,
+            detached: self.serve,
+        };
+
+        if self.serve {
+            clipboard::serve_detached(self.backend, serving, self.timeout)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        self.copy(&System::new(self.backend, serving, self.timeout))
+    }
+}
+
+impl Copy {
+    /// Copies the input to `clipboard`, or empties it with `--clear`
+    pub(super) fn copy(&self, clipboard: &dyn Clipboard) -> Result<ExitCode> {
+        if self.clear {
+            clipboard.clear()?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if self.text_fallback.is_some() && !self.html && !self.markdown {
+            bail!("--text-fallback requires --html or --markdown");
+        }
+
+        if self.image {
+            let image = self.read_image()?;
+            clipboard.set_contents(&image)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if self.base64 {
+            let encoded = STANDARD.encode(self.read_bytes("--base64")?);
+            clipboard.set_text(&encoded)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let piped = self.files.is_empty() && !stdin().is_terminal();
+        let mut input = if self.tee {
+            match tee(self.max_size)? {
+                Some(input) => input,
+                None => return Ok(ExitCode::SUCCESS),
+            }
+        } else if self.files.is_empty() {
+            match String::from_utf8(self.read_bytes("")?) {
+                Ok(input) => input,
+                Err(_) => bail!(
+                    "Not copying the input, binary contents can't be copied as text but can with --base64"
+                ),
+            }
+        } else {
+            match self.read_files() {
+                Some(contents) => contents.join(&self.separator),
+                None => return Ok(ExitCode::FAILURE),
+            }
+        };
+        if input.len() as u64 > self.max_size {
+            bail!("Input exceeds clipboard size limit of {}", self.limit());
+        }
+
+        // colored output of commands would be pasted as garbage
+        if self.strip_ansi || (piped && !self.keep_ansi) {
+            input = strip_ansi(&input);
+        }
+
+        if self.trim_all {
+            input = input.trim().to_string();
+        } else if self.trim
+            && let Some(line) = input.strip_suffix('\n')
+        {
+            input.truncate(line.strip_suffix('\r').unwrap_or(line).len());
+        }
+
+        if let Some(ending) = self.line_ending() {
+            input = String::from_utf8(ending.convert(input.as_bytes()))?;
+        }
+
+        let contents = if self.html {
+            Contents::Html {
+                text: self
+                    .text_fallback
+                    .clone()
+                    .unwrap_or_else(|| html_text(&input)),
+                html: input,
+            }
+        } else if self.markdown {
+            let mut html = String::new();
+            pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(&input));
+            Contents::Html {
+                html,
+                text: self.text_fallback.clone().unwrap_or(input),
+            }
+        } else {
+            Contents::Text(input)
+        };
+        clipboard.set_contents(&contents)?;
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Line endings the text is converted to, if any
+    fn line_ending(&self) -> Option<LineEnding> {
+        match (self.crlf, self.lf) {
+            (true, _) => Some(LineEnding::Crlf),
+            (_, true) => Some(LineEnding::Lf),
+            _ => None,
+        }
+    }
+
+    /// Contents of the given files in order, or `None` if any of them couldn't be read,
+    /// every failure being reported so that the clipboard is never left with only some of them.
+    fn read_files(&self) -> Option<Vec<String>> {
+        let mut contents = Vec::with_capacity(self.files.len());
+        let mut failed = false;
+        for file in &self.files {
+            match self.read_text(file) {
+                Ok(text) => contents.push(text),
+                Err(err) => {
+                    error!("{}", err);
+                    failed = true;
+                }
+            }
+        }
+
+        (!failed).then_some(contents)
+    }
+
+    /// Contents of the given file, or of stdin, read as they are for `flag` that only takes a single file
+    fn read_bytes(&self, flag: &str) -> Result<Vec<u8>> {
+        match self.files.as_slice() {
+            [] => match read_capped(stdin().lock(), self.max_size) {
+                Ok(Some(data)) => Ok(data),
+                Ok(None) => bail!("Input exceeds clipboard size limit of {}", self.limit()),
+                Err(err) => bail!("Failed to read from stdin: {}", err),
+            },
+            [file] => self.read_file(file),
+            _ => bail!("{} requires a single file", flag),
+        }
+    }
+
+    /// Contents of the file at `path`, refused if larger than `--max-size`
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>> {
+        match File::open(path).and_then(|file| read_capped(file, self.max_size)) {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => bail!(
+                "{:?} exceeds clipboard size limit of {}",
+                path,
+                self.limit()
+            ),
+            Err(err) => bail!("Failed to read {:?}: {}", path, err),
+        }
+    }
+
+    /// Contents of the text file at `path`, binary files, that aren't valid UTF-8 or hold NUL bytes, being refused
+    fn read_text(&self, path: &Path) -> Result<String> {
+        match String::from_utf8(self.read_file(path)?) {
+            Ok(text) if !text.contains('\0') => Ok(text),
+            _ => bail!(
+                "Not copying {:?}, binary contents can't be copied as text but can with --base64",
+                path
+            ),
+        }
+    }
+
+    /// `--max-size` as shown in errors
+    fn limit(&self) -> String {
+        format!("{} (--max-size)", human_size(self.max_size, false))
+    }
+
+    /// Image read from the given file, or from stdin, its type being told from its data
+    fn read_image(&self) -> Result<Contents> {
+        let data = self.read_bytes("--image")?;
+        match image_type(&data) {
+            Some(mime_type) => Ok(Contents::Image {
+                data,
+                mime_type: mime_type.to_string(),
+            }),
+            None => bail!("Unsupported image format, only PNG and JPEG images can be copied"),
+        }
+    }
+}
+
+/// MIME type of the image held in `data`, as told by its signature, if PNG or JPEG
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else {
+        None
+    }
+}
+
+/// Text read from stdin, written to stdout chunk by chunk as it arrives, or `None` if it was over `max_size`
+/// and isn't copied.
+///
+/// Once the reading end of stdout is closed, as with `head`, the rest of the text is still read to be copied.
+fn tee(max_size: u64) -> Result<Option<String>> {
+    let mut input = Vec::new();
+    let mut buffer = [0; 64 * 1024];
+    let (mut reader, mut out) = (stdin().lock(), stdout().lock());
+    let (mut forwarding, mut over) = (true, false);
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => bail!("Failed to read from stdin: {}", err),
+        };
+
+        let chunk = &buffer[..read];
+        if forwarding {
+            match out.write_all(chunk).and_then(|()| out.flush()) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::BrokenPipe => forwarding = false,
+                Err(err) => bail!("Failed to write to stdout: {}", err),
+            }
+        }
+
+        if !over && (input.len() + read) as u64 > max_size {
+            warn!(
+                "Not copying the input, exceeding clipboard size limit of {} (--max-size)",
+                human_size(max_size, false)
+            );
+            over = true;
+            input = Vec::new();
+        }
+        if !over {
+            input.extend_from_slice(chunk);
+        }
+    }
+
+    if over {
+        return Ok(None);
+    }
+    match String::from_utf8(input) {
+        Ok(input) => Ok(Some(input)),
+        Err(_) => bail!("Not copying the input, binary contents can't be copied as text"),
+    }
+}
+
+/// Plain text of `html`, without its tags and with its character references decoded,
+/// lines being broken where elements such as paragraphs end.
+fn html_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(['<', '&']) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('&') {
+            let reference = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+            let decoded = match reference {
+                Some("amp") => Some('&'),
+                Some("lt") => Some('<'),
+                Some("gt") => Some('>'),
+                Some("quot") => Some('"'),
+                Some("apos") => Some('\''),
+                Some("nbsp") => Some(' '),
+                Some(reference) => match reference.strip_prefix('#') {
+                    Some(code) => match code.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => code.parse().ok(),
+                    }
+                    .and_then(char::from_u32),
+                    None => None,
+                },
+                None => None,
+            };
+            match (decoded, reference) {
+                (Some(c), Some(reference)) => {
+                    text.push(c);
+                    rest = &rest[reference.len() + 2..];
+                }
+                _ => {
+                    text.push('&');
+                    rest = &rest[1..];
+                }
+            }
+            continue;
+        }
+
+        let Some(end) = rest.find('>') else {
+            text.push_str(rest);
+            rest = "";
+            break;
+        };
+        let closing = rest[1..end].starts_with('/');
+        let tag = rest[1..end].trim_start_matches('/').to_ascii_lowercase();
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        rest = &rest[end + 1..];
+
+        // nothing within these is shown
+        if matches!(name, "script" | "style" | "head") && !closing {
+            rest = match rest.to_ascii_lowercase().find(&format!("</{}", name)) {
+                Some(index) => &rest[index..],
+                None => "",
+            };
+            continue;
+        }
+        if matches!(
+            name,
+            "br" | "p"
+                | "div"
+                | "li"
+                | "tr"
+                | "h1"
+                | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "pre"
+                | "blockquote"
+        ) && !text.is_empty()
+            && !text.ends_with('\n')
+        {
+            text.push('\n');
+        }
+    }
+    text.push_str(rest);
+
+    text.trim_end().to_string()
+}
+
+/// `text` without the ANSI escape sequences it holds, such as CSI ones setting colors and OSC ones
+/// making hyperlinks, leaving the visible text exactly as it was.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let introducer = match c {
+            '\x1b' => match chars.next() {
+                Some(c) => c,
+                None => break,
+            },
+            // 8-bit CSI and OSC
+            '\u{9b}' => '[',
+            '\u{9d}' => ']',
+            c => {
+                stripped.push(c);
+                continue;
+            }
+        };
+
+        match introducer {
+            // parameters and intermediate bytes, up to the final one
+            '[' => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // strings ended by BEL or ST, as OSC, DCS, SOS, PM and APC ones are
+            ']' | 'P' | 'X' | '^' | '_' => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || c == '\u{9c}' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.next_if_eq(&'\\').is_some() {
+                        break;
+                    }
+                }
+            }
+            // intermediate bytes, such as the one selecting a character set, up to the final one
+            '\x20'..='\x2f' => {
+                while chars.next_if(|c| ('\x20'..='\x2f').contains(c)).is_some() {}
+                chars.next();
+            }
+            // two characters sequences, such as the one resetting the terminal
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// Whole contents of `reader`, or `None` once more than `max_size` bytes are read, leaving the rest unread
+fn read_capped(reader: impl Read, max_size: u64) -> std::io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut data)?;
+    Ok((data.len() as u64 <= max_size).then_some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clipboard::Mock;
+    use clap::Parser;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        copy: Copy,
+    }
+
+    /// Copy with the given arguments, as given on the command line
+    fn copy(args: &[&str]) -> Copy {
+        Cli::parse_from(["copy"].iter().chain(args)).copy
+    }
+
+    /// Path of a file named `name` holding `contents` in `dir`
+    fn file(dir: &TempDir, name: &str, contents: &[u8]) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Contents copied from a file holding `contents`, with the given arguments
+    fn copied(contents: &[u8], args: &[&str]) -> Option<Contents> {
+        let dir = TempDir::new().unwrap();
+        let path = file(&dir, "input", contents);
+        let clipboard = Mock::default();
+        let mut args = args.to_vec();
+        args.push(&path);
+        copy(&args).copy(&clipboard).unwrap();
+        clipboard.contents()
+    }
+
+    #[test]
+    fn copies_files() {
+        assert_eq!(
+            copied(b"text", &[]),
+            Some(Contents::Text("text".to_string()))
+        );
+
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (file(&dir, "a", b"a"), file(&dir, "b", b"b"));
+        let clipboard = Mock::default();
+        copy(&[&a, &b]).copy(&clipboard).unwrap();
+        assert_eq!(
+            clipboard.contents(),
+            Some(Contents::Text("a\nb".to_string()))
+        );
+        copy(&["--separator", ", ", &a, &b])
+            .copy(&clipboard)
+            .unwrap();
+        assert_eq!(
+            clipboard.contents(),
+            Some(Contents::Text("a, b".to_string()))
+        );
+    }
+
+    #[test]
+    fn refuses_binary_files() {
+        let dir = TempDir::new().unwrap();
+        let path = file(&dir, "binary", b"\0\xff");
+        let clipboard = Mock::default();
+        assert_eq!(copy(&[&path]).copy(&clipboard).unwrap(), ExitCode::FAILURE);
+        assert_eq!(clipboard.contents(), None);
+    }
+
+    #[test]
+    fn refuses_files_over_max_size() {
+        let dir = TempDir::new().unwrap();
+        let path = file(&dir, "large", b"12345");
+        let clipboard = Mock::default();
+        assert_eq!(
+            copy(&["--max-size", "4", &path]).copy(&clipboard).unwrap(),
+            ExitCode::FAILURE
+        );
+        assert!(copy(&["--max-size", "5", &path]).copy(&clipboard).is_ok());
+    }
+
+    #[test]
+    fn clears() {
+        let clipboard = Mock::with(Contents::Text("text".to_string()));
+        copy(&["--clear"]).copy(&clipboard).unwrap();
+        assert_eq!(clipboard.contents(), None);
+    }
+
+    #[test]
+    fn copies_html_along_with_text() {
+        assert_eq!(
+            copied(b"<p>a &amp; b</p><p>c</p>", &["--html"]),
+            Some(Contents::Html {
+                html: "<p>a &amp; b</p><p>c</p>".to_string(),
+                text: "a & b\nc".to_string(),
+            })
+        );
+        assert_eq!(
+            copied(b"*a*", &["--markdown", "--text-fallback", "b"]),
+            Some(Contents::Html {
+                html: "<p><em>a</em></p>\n".to_string(),
+                text: "b".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn copies_images() {
+        let png = b"\x89PNG\r\n\x1a\ndata";
+        assert_eq!(
+            copied(png, &["--image"]),
+            Some(Contents::Image {
+                data: png.to_vec(),
+                mime_type: "image/png".to_string(),
+            })
+        );
+
+        let dir = TempDir::new().unwrap();
+        let path = file(&dir, "text", b"text");
+        assert!(copy(&["--image", &path]).copy(&Mock::default()).is_err());
+    }
+
+    #[test]
+    fn converts_line_endings() {
+        assert_eq!(
+            copied(b"a\nb\r\n", &["--crlf"]),
+            Some(Contents::Text("a\r\nb\r\n".to_string()))
+        );
+        assert_eq!(
+            copied(b"a\r\nb\n", &["--lf"]),
+            Some(Contents::Text("a\nb\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn trims_the_input() {
+        let text = |text: &str| Some(Contents::Text(text.to_string()));
+        for (input, trimmed, trimmed_all) in [
+            ("/home/user\n", "/home/user", "/home/user"),
+            ("/home/user\r\n", "/home/user", "/home/user"),
+            ("a\n\n", "a\n", "a"),
+            ("a\r\n\r\n", "a\r\n", "a"),
+            ("  a b  \n", "  a b  ", "a b"),
+            ("\t a\nb \r\n", "\t a\nb ", "a\nb"),
+            ("a\r", "a\r", "a"),
+            ("a", "a", "a"),
+            ("\n", "", ""),
+            (" \t\r\n \n", " \t\r\n ", ""),
+            ("", "", ""),
+        ] {
+            assert_eq!(
+                copied(input.as_bytes(), &[]),
+                text(input),
+                "copying {input:?}"
+            );
+            assert_eq!(
+                copied(input.as_bytes(), &["--trim"]),
+                text(trimmed),
+                "copying {input:?} with --trim"
+            );
+            assert_eq!(
+                copied(input.as_bytes(), &["-t"]),
+                text(trimmed),
+                "copying {input:?} with -t"
+            );
+            assert_eq!(
+                copied(input.as_bytes(), &["--trim-all"]),
+                text(trimmed_all),
+                "copying {input:?} with --trim-all"
+            );
         }
 
-        ClipboardContext::new()
-            .and_then(|mut ctx| ctx.set_contents(input))
-            .map_err(|err| eyre!("Failed to set clipboard contents: {}", err))
+        // files are trimmed once joined, as is converted text
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (file(&dir, "a", b" a\n"), file(&dir, "b", b"b\n"));
+        let clipboard = Mock::default();
+        copy(&["--trim-all", &a, &b]).copy(&clipboard).unwrap();
+        assert_eq!(clipboard.contents(), text("a\n\nb"));
+        assert_eq!(copied(b"a\nb\n", &["--trim", "--crlf"]), text("a\r\nb"));
+        assert!(Cli::try_parse_from(["copy", "--trim", "--trim-all"]).is_err());
     }
 }
@@ -0,0 +1,186 @@
+use color_eyre::eyre::Result;
+use console::{Key, Term};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{Hog, Items, Usage, report::ellipsize};
+use crate::utils::{
+    size::human_size,
+    style::{Style, Styler},
+};
+
+/// Keys shown at the bottom of the screen
+const HELP: &str = "↑↓ move  Enter open  Backspace up  d print path and quit  q quit";
+
+/// Terminal taken over while browsing or watching, given back as it was once dropped, even when panicking
+pub(super) struct Screen {
+    term: Term,
+}
+
+impl Screen {
+    /// Switches to the alternate screen, with the cursor hidden
+    pub(super) fn enter(term: Term) -> Result<Self> {
+        let mut screen = Self { term };
+        write!(screen.term, "\x1b[?1049h")?;
+        screen.term.hide_cursor()?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.term.show_cursor();
+        let _ = write!(self.term, "\x1b[?1049l");
+        let _ = self.term.flush();
+    }
+}
+
+/// A directory being browsed
+struct Level {
+    path: PathBuf,
+    usage: Usage,
+    entries: Items,
+    selected: usize,
+}
+
+impl Hog {
+    /// Lets the user browse the scanned directory at `root` with the arrow keys, entering and leaving
+    /// the directories found in `tree`, and gives the path selected with `d`, if any.
+    ///
+    /// Browsing is read-only, drawing on the alternate screen of stderr so that stdout is left for the path.
+    pub(super) fn browse(
+        &self,
+        root: &Path,
+        total: &Usage,
+        mut tree: HashMap<PathBuf, Items>,
+        styler: Styler,
+    ) -> Result<Option<PathBuf>> {
+        let screen = Screen::enter(Term::stderr())?;
+        let term = &screen.term;
+
+        let mut levels = vec![self.level(root, total, &mut tree)];
+        loop {
+            let entered = levels.len() > 1;
+            let level = levels.last_mut().unwrap();
+            self.draw(term, level, styler)?;
+
+            match term.read_key_raw()? {
+                Key::ArrowUp | Key::Char('k') => level.selected = level.selected.saturating_sub(1),
+                Key::ArrowDown | Key::Char('j') => {
+                    level.selected = (level.selected + 1).min(level.entries.len().saturating_sub(1))
+                }
+                Key::Home => level.selected = 0,
+                Key::End => level.selected = level.entries.len().saturating_sub(1),
+                Key::Enter | Key::ArrowRight | Key::Char('l') => {
+                    if let Some((path, usage)) = level.entries.get(level.selected)
+                        && usage.dirs > 0
+                    {
+                        let (path, usage) = (path.clone(), usage.clone());
+                        levels.push(self.level(&path, &usage, &mut tree));
+                    }
+                }
+                Key::Backspace | Key::ArrowLeft | Key::Char('h') if entered => {
+                    // left directories are kept as they were, to be entered again
+                    let level = levels.pop().unwrap();
+                    tree.insert(level.path, level.entries);
+                }
+                Key::Char('d') => {
+                    let selected = match level.entries.get(level.selected) {
+                        Some((path, _)) => path.clone(),
+                        None => level.path.clone(),
+                    };
+                    return Ok(Some(selected));
+                }
+                Key::Char('q') | Key::Escape | Key::CtrlC => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+
+    /// Directory at `path` to be browsed, its entries taken from `tree` being filtered by age and sorted
+    fn level(&self, path: &Path, usage: &Usage, tree: &mut HashMap<PathBuf, Items>) -> Level {
+        let mut entries: Items = tree
+            .remove(path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, usage)| self.matches_age(usage))
+            .collect();
+        self.sort(&mut entries);
+
+        Level {
+            path: path.to_path_buf(),
+            usage: usage.clone(),
+            entries,
+            selected: 0,
+        }
+    }
+
+    /// Draws the entries of `level` around the selected one, with their size and share of the directory
+    fn draw(&self, term: &Term, level: &Level, styler: Styler) -> Result<()> {
+        let (height, width) = term.size();
+        let (height, width) = (height as usize, width as usize);
+        let size = |size: usize| human_size(size as u64, self.si);
+
+        let mut lines = vec![styler.paint(
+            ellipsize(
+                &format!(
+                    "{}: {}",
+                    level.path.to_string_lossy(),
+                    size(level.usage.size)
+                ),
+                width,
+            ),
+            Style::BoldBlue,
+        )];
+
+        // the selected entry is kept in view, between the header and the help line
+        let rows = height.saturating_sub(3).max(1);
+        let first = level.selected.saturating_sub(rows - 1);
+        for (index, (path, usage)) in level.entries.iter().enumerate().skip(first).take(rows) {
+            let name = path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy();
+            let share = match level.usage.size {
+                0 => 0.0,
+                total => usage.size as f64 * 100.0 / total as f64,
+            };
+            let suffix = if usage.dirs > 0 { "/" } else { "" };
+            let details = format!(": {} ({:.1}%)", size(usage.size), share);
+            let name_width = width.saturating_sub(details.chars().count() + suffix.len() + 3);
+
+            let line = format!(
+                "{} {}{}{}",
+                if index == level.selected { '>' } else { ' ' },
+                ellipsize(&name, name_width),
+                suffix,
+                details
+            );
+            lines.push(if index == level.selected {
+                styler.paint(line, Style::Reverse)
+            } else {
+                line
+            });
+        }
+        if level.entries.is_empty() {
+            lines.push("  (empty)".to_string());
+        }
+
+        term.clear_screen()?;
+        for line in lines {
+            term.write_line(&line)?;
+        }
+        term.move_cursor_to(0, height.saturating_sub(1))?;
+        write!(
+            &*term,
+            "{}",
+            styler.paint(ellipsize(HELP, width), Style::Dim)
+        )?;
+        term.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use color_eyre::eyre::{Result, bail, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{create_dir_all, read_to_string, remove_file, rename, write},
+    io::ErrorKind,
+    path::{Path, PathBuf, absolute},
+    sync::Mutex,
+    time::SystemTime,
+};
+use tracing::{debug, warn};
+
+use super::{Hog, Usage};
+
+/// Version of the cache format, to be increased whenever it changes incompatibly
+const VERSION: u32 = 2;
+
+/// Options changing the size of a directory, sizes cached with other ones being useless
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Options {
+    apparent_size: bool,
+    count_links: bool,
+    no_hidden: bool,
+    one_file_system: bool,
+    include_virtual: bool,
+    exclude: Vec<String>,
+}
+
+impl Options {
+    pub fn of(hog: &Hog) -> Self {
+        Self {
+            apparent_size: hog.apparent_size,
+            count_links: hog.count_links,
+            no_hidden: hog.no_hidden,
+            one_file_system: hog.one_file_system,
+            include_virtual: hog.include_virtual,
+            exclude: hog.exclude.clone(),
+        }
+    }
+}
+
+/// Contents of the cache file
+#[derive(Debug, Serialize, Deserialize)]
+struct Contents {
+    version: u32,
+    options: Options,
+    /// Cached directories by absolute path
+    dirs: HashMap<String, Cached>,
+}
+
+/// Usage of a whole directory, valid as long as the directory has the same modification time and number of entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Cached {
+    modified: SystemTime,
+    children: usize,
+    usage: Usage,
+    /// Files with several hard links counted within, left out of the serialized usage
+    linked: Vec<(u64, u64)>,
+}
+
+/// Usage of the directories computed by previous scans with `--cache`, reused for the unchanged ones
+#[derive(Debug)]
+pub(super) struct Cache {
+    path: PathBuf,
+    options: Options,
+    /// Directories computed by previous scans, read from the cache file or saved by this process
+    previous: Mutex<HashMap<String, Cached>>,
+    /// Directories computed by the current scan, replacing the previous ones once saved
+    current: Mutex<HashMap<String, Cached>>,
+}
+
+impl Cache {
+    /// `$XDG_CACHE_HOME/dsu/hog.json`, falling back to `~/.cache/dsu/hog.json`
+    pub fn path() -> Option<PathBuf> {
+        let cache_dir = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+        Some(cache_dir.join("dsu").join("hog.json"))
+    }
+
+    /// Reads the cache of scans made with the same `options`, starting over from an empty one
+    /// if it's missing, unreadable, or was written with other options or another version of the format.
+    pub fn load(options: Options) -> Result<Self> {
+        let path = Self::path().ok_or_else(|| eyre!("Unable to determine the cache directory"))?;
+        Ok(Self::load_from(path, options))
+    }
+
+    /// Reads the cache file at `path`, as [`Cache::load`] does
+    pub fn load_from(path: PathBuf, options: Options) -> Self {
+        let previous = match read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<Contents>(&contents) {
+                Ok(contents) if contents.version == VERSION && contents.options == options => {
+                    contents.dirs
+                }
+                Ok(_) => {
+                    debug!("Not reusing cache {:?}, written with other options", path);
+                    HashMap::new()
+                }
+                Err(err) => {
+                    warn!("Ignoring cache {:?}, failed to parse it: {}", path, err);
+                    HashMap::new()
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                warn!("Ignoring cache {:?}, failed to read it: {}", path, err);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            options,
+            previous: Mutex::new(previous),
+            current: Mutex::default(),
+        }
+    }
+
+    /// Usage of the whole directory at `path` as cached, if it still has the same modification time
+    /// and number of entries.
+    ///
+    /// Changes deeper within the directory that leave both unchanged, such as a file of a subdirectory
+    /// growing, are not noticed.
+    pub fn get(&self, path: &Path, modified: SystemTime, children: usize) -> Option<Usage> {
+        let previous = self.previous.lock().unwrap();
+        let cached = previous.get(&key(path)?)?;
+        (cached.modified == modified && cached.children == children).then(|| Usage {
+            linked: cached.linked.clone(),
+            ..cached.usage.clone()
+        })
+    }
+
+    /// Caches the usage of the whole directory at `path`, found with the given modification time and number of entries
+    pub fn insert(&self, path: &Path, modified: SystemTime, children: usize, usage: &Usage) {
+        if let Some(key) = key(path) {
+            self.current.lock().unwrap().insert(
+                key,
+                Cached {
+                    modified,
+                    children,
+                    usage: usage.clone(),
+                    linked: usage.linked.clone(),
+                },
+            );
+        }
+    }
+
+    /// Writes the directories computed by the current scan to the cache file, along with the previous ones
+    /// that weren't scanned again, replacing it as a whole so that concurrent scans never read half of it.
+    ///
+    /// The directories computed become previous ones, reused by the next scans of the same run when watching.
+    pub fn save(&self) -> Result<()> {
+        let mut previous = self.previous.lock().unwrap();
+        previous.extend(self.current.lock().unwrap().drain());
+        let contents = Contents {
+            version: VERSION,
+            options: self.options.clone(),
+            dirs: previous.clone(),
+        };
+
+        let temporary = self
+            .path
+            .with_extension(format!("json.{}", std::process::id()));
+        if let Some(dir) = self.path.parent()
+            && let Err(err) = create_dir_all(dir)
+        {
+            bail!("Failed to create cache directory {:?}: {}", dir, err);
+        }
+        if let Err(err) = write(&temporary, serde_json::to_string(&contents)?) {
+            bail!("Failed to write cache {:?}: {}", temporary, err);
+        }
+        if let Err(err) = rename(&temporary, &self.path) {
+            let _ = remove_file(&temporary);
+            bail!("Failed to write cache {:?}: {}", self.path, err);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the cache file, giving its path if there was one
+    pub fn clear() -> Result<Option<PathBuf>> {
+        let path = Self::path().ok_or_else(|| eyre!("Unable to determine the cache directory"))?;
+
+        match remove_file(&path) {
+            Ok(()) => Ok(Some(path)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => bail!("Failed to remove cache {:?}: {}", path, err),
+        }
+    }
+}
+
+/// Key of the directory at `path` in the cache, directories whose path isn't valid UTF-8 never being cached
+fn key(path: &Path) -> Option<String> {
+    absolute(path).ok()?.to_str().map(str::to_string)
+}
@@ -0,0 +1,1341 @@
+mod browse;
+mod cache;
+mod mounts;
+mod progress;
+mod report;
+mod snapshot;
+mod watch;
+
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{Result, bail};
+use console::Term;
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::{metadata, symlink_metadata},
+    io::{self, ErrorKind, IsTerminal, Write, stderr, stdin, stdout},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Mutex, mpsc},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, error, info, warn};
+use uzers::get_user_by_uid;
+
+use crate::cli::{Context, Runnable};
+use crate::utils::matcher::{Matcher, match_names};
+use crate::utils::size::{human_size, parse_size};
+use crate::utils::style::Styler;
+use cache::{Cache, Options};
+use progress::Progress;
+use report::{Output, count};
+use snapshot::Snapshot;
+
+/// Exit code of scans with `--strict` that left out entries which couldn't be read
+const EXIT_PARTIAL: u8 = 8;
+
+/// Exit code of scans with `--fail-over` whose total size is over the limit
+const EXIT_OVER_LIMIT: u8 = 9;
+
+#[derive(Args, Debug)]
+pub struct Hog {
+    /// Directories to analyze, listing their immediate entries if given a single one
+    /// or one entry per directory otherwise
+    #[arg(default_value = ".")]
+    pub dirs: Vec<PathBuf>,
+
+    /// Only print the total size, along with that of each directory if given several, like `du -s`
+    #[arg(
+        long,
+        short = 's',
+        conflicts_with_all = ["files", "by_type", "by_owner", "depth", "dirs_only", "files_only", "older_than", "newer_than"]
+    )]
+    pub summarize: bool,
+
+    /// Human readable sizes
+    #[arg(long, short = 'H', default_value = "false")]
+    pub human_readable: bool,
+
+    /// Number of items to show, 0 showing all of them
+    #[arg(long, short, default_value = "10")]
+    pub limit: usize,
+
+    /// Order in which items are shown, applied before the limit
+    #[arg(long, value_enum, default_value_t = Sort::Size)]
+    pub sort: Sort,
+
+    /// Reverse the sorting order
+    #[arg(long, short = 'r')]
+    pub reverse: bool,
+
+    /// Show the running share of the total in the shown order instead of the share of each item
+    #[arg(long)]
+    pub cumulative: bool,
+
+    /// Draw a bar next to each item, scaled to the largest one, when printing text to a terminal
+    #[arg(long, short = 'g')]
+    pub graph: bool,
+
+    /// Format of the printed items, `json` and `csv` always giving sizes in bytes
+    #[arg(long, short = 'o', value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+
+    /// Print the paths of the shown items terminated by NUL characters, same as `--output nul`
+    #[arg(long, short = '0', conflicts_with = "output")]
+    pub print0: bool,
+
+    /// Print paths relative to the analyzed directory, given a single one
+    #[arg(long)]
+    pub relative: bool,
+
+    /// Skip entries matching the glob pattern and don't scan them, can be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Follow symlinks, counting what they point to instead of the links themselves
+    #[arg(long, short = 'L')]
+    pub follow_symlinks: bool,
+
+    /// Skip hidden files and directories, only tallying their size apart from the total
+    #[arg(long)]
+    pub no_hidden: bool,
+
+    /// Stay on the filesystem of the analyzed directory, skipping whatever is mounted within it
+    #[arg(long, short = 'x')]
+    pub one_file_system: bool,
+
+    /// Also scan pseudo filesystems such as `/proc` and `/sys`, which are skipped otherwise
+    #[arg(long)]
+    pub include_virtual: bool,
+
+    /// List the largest files found anywhere in the directory instead of its immediate entries
+    #[arg(long)]
+    pub files: bool,
+
+    /// Only show entries that are directories, the total still counting everything
+    #[arg(long, conflicts_with_all = ["files_only", "files", "by_type"])]
+    pub dirs_only: bool,
+
+    /// Only show entries that aren't directories, whose contents are then not scanned
+    #[arg(long, conflicts_with_all = ["files", "by_type"])]
+    pub files_only: bool,
+
+    /// Group the usage of the whole directory by file extension instead of by entry
+    #[arg(long, conflicts_with = "files")]
+    pub by_type: bool,
+
+    /// Group the usage of the whole directory by the owner of files and directories instead of by entry
+    #[arg(long, conflicts_with_all = ["files", "by_type", "dirs_only", "files_only"])]
+    pub by_owner: bool,
+
+    /// Use powers of 1000 (KB, MB, ...) for human readable sizes instead of 1024 (KiB, MiB, ...)
+    #[arg(long)]
+    pub si: bool,
+
+    /// Count apparent sizes, the number of bytes files hold, instead of the disk space allocated to them.
+    ///
+    /// Disk usage, as given by `du` and `df`, counts whole blocks and leaves out the holes of sparse files,
+    /// so that small files use more than their apparent size while sparse and compressed ones use less.
+    #[arg(long)]
+    pub apparent_size: bool,
+
+    /// Count every hard link to a file, instead of counting the file once
+    #[arg(long)]
+    pub count_links: bool,
+
+    /// Depth of the shown entries below the directory, deeper ones being counted in them, 0 only showing the total
+    #[arg(long, short = 'e', default_value = "1")]
+    pub depth: usize,
+
+    /// Only show entries whose contents were all last modified longer ago than the duration, such as `1y`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub older_than: Option<Duration>,
+
+    /// Only show entries with contents modified more recently than the duration, such as `30d`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub newer_than: Option<Duration>,
+
+    /// Save the sizes of all the entries found to a JSON snapshot, to be compared against with `--diff`
+    #[arg(long, value_name = "FILE", conflicts_with = "summarize")]
+    pub save: Option<PathBuf>,
+
+    /// Show how much each entry grew or shrank since a snapshot saved with `--save`, instead of its size
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["summarize", "older_than", "newer_than"]
+    )]
+    pub diff: Option<PathBuf>,
+
+    /// Scan again every given number of seconds, redrawing the listing
+    /// and highlighting entries whose size changed, until interrupted
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "5",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["output", "print0", "save", "diff", "fail_over"]
+    )]
+    pub watch: Option<u64>,
+
+    /// Browse the scanned directory in the terminal, printing the selected path on exit with `d`
+    #[arg(
+        long,
+        short = 'i',
+        conflicts_with_all = [
+            "summarize", "files", "by_type", "by_owner", "dirs_only", "files_only", "depth",
+            "output", "print0", "watch", "save", "diff"
+        ]
+    )]
+    pub interactive: bool,
+
+    /// Reuse the sizes of directories left unchanged since the previous scan with `--cache`,
+    /// whose modification time and number of entries are the same.
+    ///
+    /// Changes deeper within such directories that leave both unchanged, such as a file growing
+    /// in one of their subdirectories, are missed until the cache is cleared.
+    #[arg(
+        long,
+        conflicts_with_all = ["no_cache", "files", "by_type", "by_owner", "follow_symlinks", "interactive"]
+    )]
+    pub cache: bool,
+
+    /// Scan everything again without reading nor updating the cache, even if enabled in the config file
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Remove the cache of previous scans with `--cache` and exit
+    #[arg(long)]
+    pub clear_cache: bool,
+
+    /// Exit with a dedicated code if the total size is over the given size, such as `20G` or `500MiB`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub fail_over: Option<u64>,
+
+    /// Exit with a dedicated code if any entry couldn't be read, the sizes shown being partial
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Number of threads used to scan directories [default: number of CPUs]
+    #[arg(long, short = 'j', value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+
+    #[arg(skip)]
+    excludes: Option<Matcher>,
+
+    #[arg(skip)]
+    styler: Styler,
+
+    /// Devices of the pseudo filesystems skipped unless `--include-virtual` is given
+    #[arg(skip)]
+    virtual_devices: HashSet<u64>,
+
+    /// Entries of the directories read by previous scans when watching
+    #[arg(skip)]
+    listings: Option<Mutex<HashMap<PathBuf, Listing>>>,
+
+    /// Entries found in every directory with their usage, kept to be browsed with `--interactive`
+    #[arg(skip)]
+    tree: Option<Mutex<HashMap<PathBuf, Items>>>,
+
+    /// Usage of the directories computed by previous scans, reused with `--cache`
+    #[arg(skip)]
+    cached: Option<Cache>,
+}
+
+/// Order of the analyzed items
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Sort {
+    /// Largest first
+    #[default]
+    Size,
+    /// Alphabetical by path
+    Name,
+    /// Most files contained first
+    Count,
+    /// Most recently modified contents first
+    Mtime,
+}
+
+/// Disk usage of an item of the analyzed directory, including all of its contents
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Usage {
+    /// Size in bytes as counted, either the apparent size or the disk usage
+    size: usize,
+    /// Number of bytes held by files
+    apparent_size: usize,
+    /// Number of bytes allocated on disk
+    disk_size: usize,
+    /// Size of the files left out as already counted through another hard link or symlink
+    deduplicated: usize,
+    /// Size of the hidden entries left out with `--no-hidden`, including their contents
+    hidden: usize,
+    /// Number of files, the item itself included if it isn't a directory
+    files: usize,
+    /// Number of directories, the item itself included if it is one
+    dirs: usize,
+    /// Most recent modification time found, that of the item itself included
+    modified: Option<SystemTime>,
+    /// Whether some of the contents couldn't be read, the usage being partial
+    #[serde(skip)]
+    partial: bool,
+    /// Device and inode of the files with several hard links counted within, only kept with `--cache`
+    /// so that reusing the cached size of a directory still keeps them from being counted again elsewhere
+    #[serde(skip)]
+    linked: Vec<(u64, u64)>,
+}
+
+/// Analyzed items, either entries, files or extensions, along with their usage
+type Items = Vec<(PathBuf, Usage)>;
+
+/// Entries of a directory to be scanned, as read at its last modification time
+#[derive(Debug, Clone, Default)]
+struct Listing {
+    modified: Option<SystemTime>,
+    paths: Vec<PathBuf>,
+    /// Hidden entries left out with `--no-hidden`, only to be tallied
+    hidden: Vec<PathBuf>,
+    /// Whether every entry could be read
+    complete: bool,
+}
+
+/// State of a scan of the analyzed directory, shared by the threads scanning it
+#[derive(Debug, Default)]
+struct Scan {
+    /// Files and directories already counted, when following symlinks or counting hard links once
+    visited: Mutex<HashSet<(u64, u64)>>,
+    /// Entries found at the shown depth
+    entries: Mutex<Vec<(PathBuf, Usage)>>,
+    /// Usage by file extension with `--by-type`
+    types: Mutex<HashMap<String, Usage>>,
+    /// Usage by user id of the owner with `--by-owner`
+    owners: Mutex<HashMap<u32, Usage>>,
+    /// Largest files found with `--files`, the smallest of them on top of the heap
+    largest: Mutex<BinaryHeap<Reverse<(usize, PathBuf, Usage)>>>,
+    /// Progress shown while scanning, only on a terminal
+    progress: Option<Progress>,
+    /// Number of entries that couldn't be read, by kind of error
+    unreadable: Mutex<HashMap<ErrorKind, usize>>,
+}
+
+impl Scan {
+    /// Counts an entry that couldn't be read because of `err`
+    fn unreadable(&self, err: &io::Error) {
+        *self
+            .unreadable
+            .lock()
+            .unwrap()
+            .entry(err.kind())
+            .or_default() += 1;
+    }
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.size += other.size;
+        self.apparent_size += other.apparent_size;
+        self.disk_size += other.disk_size;
+        self.deduplicated += other.deduplicated;
+        self.hidden += other.hidden;
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.modified = self.modified.max(other.modified);
+        self.partial |= other.partial;
+        self.linked.extend(other.linked);
+    }
+}
+
+impl Runnable for Hog {
+    fn run(&mut self, ctx: &Context) -> Result<ExitCode> {
+        if self.clear_cache {
+            match Cache::clear()? {
+                Some(path) => info!("Removed cache {:?}", path),
+                None => info!("No cache to remove"),
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let invalid: Vec<String> = self
+            .dirs
+            .iter()
+            .filter(|dir| !dir.is_dir())
+            .map(|dir| format!("{:?}", dir))
+            .collect();
+        if !invalid.is_empty() {
+            bail!("Not a directory: {}", invalid.join(", "));
+        }
+        if self.relative && self.dirs.len() > 1 {
+            bail!("--relative requires a single directory");
+        }
+        if self.print0 {
+            self.output = Output::Nul;
+        }
+
+        self.excludes = Matcher::new(&self.exclude)?;
+        self.styler = Styler::new(ctx.color, &stdout());
+        if !self.include_virtual {
+            self.virtual_devices = mounts::virtual_devices();
+        }
+        // read first, so that a snapshot can be compared against and replaced in the same run
+        let previous = self.diff.as_deref().map(Snapshot::load).transpose()?;
+        // scans needing every entry, and not only the usage of directories, never use the cache enabled in the config file
+        let cacheable = !self.files
+            && !self.by_type
+            && !self.by_owner
+            && !self.follow_symlinks
+            && !self.interactive;
+        if !self.no_cache && (self.cache || ctx.config.hog.cache.unwrap_or(false)) && cacheable {
+            self.cached = Some(Cache::load(Options::of(self))?);
+        }
+        // only the directories themselves are kept, when given several
+        if self.summarize {
+            self.depth = usize::from(self.dirs.len() > 1);
+        }
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.jobs.map_or(0, usize::from))
+            .build()?;
+
+        if self.interactive {
+            if self.dirs.len() > 1 {
+                bail!("--interactive requires a single directory");
+            }
+            if !Term::stderr().is_term() || !stdin().is_terminal() {
+                bail!("--interactive requires a terminal");
+            }
+            self.tree = Some(Mutex::default());
+            let (total, _, unreadable) = self.scan(&pool, ctx);
+            let tree = self.tree.take().unwrap().into_inner().unwrap();
+
+            let selected = self.browse(
+                &self.dirs[0],
+                &total,
+                tree,
+                Styler::new(ctx.color, &stderr()),
+            )?;
+            if let Some(selected) = selected {
+                let mut out = stdout().lock();
+                out.write_all(selected.as_os_str().as_bytes())?;
+                out.write_all(b"\n")?;
+            }
+            return Ok(self.finish(&total, &[], unreadable));
+        }
+
+        if let Some(interval) = self.watch {
+            if !stdout().is_terminal() {
+                bail!("--watch requires a terminal");
+            }
+            self.listings = Some(Mutex::default());
+            return self.watch(&pool, ctx, Duration::from_secs(interval));
+        }
+
+        let (total, items, unreadable) = self.scan(&pool, ctx);
+
+        if let Some(save) = &self.save {
+            Snapshot::new(&self.dirs, &total, &items).save(save)?;
+        }
+
+        if let Some(previous) = previous {
+            let mut changes = previous.diff(&items);
+            if self.limit > 0 {
+                changes.truncate(self.limit);
+            }
+            self.output.print_diff(self, &total, &previous, &changes)?;
+            return Ok(self.finish(&total, &items, unreadable));
+        }
+
+        let (items, filtered) = self.shown(items);
+        self.output
+            .print(self, &total, &items, &filtered, &HashMap::new())?;
+
+        Ok(self.finish(&total, &items, unreadable))
+    }
+}
+
+impl Hog {
+    /// Scans the analyzed directories, giving their total usage, the items found, that are either entries,
+    /// files or extensions, and the number of entries that couldn't be read by kind of error.
+    fn scan(&self, pool: &ThreadPool, ctx: &Context) -> (Usage, Items, HashMap<ErrorKind, usize>) {
+        let scan = Scan {
+            progress: (!ctx.quiet && stderr().is_terminal()).then(Progress::default),
+            ..Default::default()
+        };
+        let total = thread::scope(|scope| {
+            // the progress line is cleared once the scan is done, before anything is printed
+            let (stop, stopped) = mpsc::channel();
+            if let Some(progress) = &scan.progress {
+                scope.spawn(|| progress.draw(stopped));
+            }
+
+            let total = pool.install(|| self.total(&scan));
+            drop(stop);
+            total
+        });
+        let unreadable = scan.unreadable.into_inner().unwrap();
+        // failing to update the cache only makes the next scan slower
+        if let Some(cached) = &self.cached
+            && let Err(err) = cached.save()
+        {
+            warn!("{}", err);
+        }
+
+        let mut items = scan.entries.into_inner().unwrap();
+        if self.summarize
+            && let [dir] = self.dirs.as_slice()
+        {
+            items = vec![(dir.clone(), total.clone())];
+        }
+        if self.files {
+            items = scan
+                .largest
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(|Reverse((_, path, usage))| match self.dirs.as_slice() {
+                    [dir] => (path.strip_prefix(dir).unwrap_or(&path).to_path_buf(), usage),
+                    _ => (path, usage),
+                })
+                .collect();
+        }
+        if self.by_type {
+            items = scan
+                .types
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(|(kind, usage)| (PathBuf::from(kind), usage))
+                .collect();
+        }
+        if self.by_owner {
+            items = scan
+                .owners
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(|(uid, usage)| (PathBuf::from(owner_name(uid)), usage))
+                .collect();
+        }
+
+        // extensions and owners aren't paths
+        if self.relative
+            && !self.by_type
+            && !self.by_owner
+            && let [dir] = self.dirs.as_slice()
+        {
+            for (path, _) in &mut items {
+                if let Ok(relative) = path.strip_prefix(dir) {
+                    *path = relative.to_path_buf();
+                }
+            }
+        }
+
+        (total, items, unreadable)
+    }
+
+    /// Total usage of the analyzed directories
+    fn total(&self, scan: &Scan) -> Usage {
+        // several directories are entries themselves, one level above the entries of a single one
+        match self.dirs.as_slice() {
+            [dir] => self.disk_usage(dir, dir, 0, None, scan),
+            dirs => dirs
+                .par_iter()
+                .map(|dir| self.disk_usage(dir, dir, 1, None, scan))
+                .reduce(Usage::default, |mut total, usage| {
+                    total.add(usage);
+                    total
+                }),
+        }
+    }
+
+    /// Items to be shown, filtered by age, sorted and limited, along with those filtered out.
+    ///
+    /// Summarized directories are all shown in the order they were given instead.
+    fn shown(&self, mut items: Items) -> (Items, Items) {
+        if self.summarize {
+            items.sort_by_key(|(path, _)| self.dirs.iter().position(|dir| dir == path));
+            return (items, Vec::new());
+        }
+
+        let (mut items, filtered) = items
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, usage)| self.matches_age(usage));
+
+        self.sort(&mut items);
+
+        let limit = match self.limit {
+            0 => usize::MAX,
+            limit => limit,
+        };
+        items.truncate(limit);
+
+        (items, filtered)
+    }
+
+    /// Warns about the entries that couldn't be read and a total size over `--fail-over`,
+    /// once everything is printed, and gives the exit code of the scan.
+    fn finish(
+        &self,
+        total: &Usage,
+        items: &[(PathBuf, Usage)],
+        unreadable: HashMap<ErrorKind, usize>,
+    ) -> ExitCode {
+        let partial = !unreadable.is_empty();
+        if partial {
+            let count_unreadable = unreadable.values().sum();
+            let reasons = match unreadable.len() {
+                1 => unreadable.keys().map(ErrorKind::to_string).collect(),
+                _ => {
+                    let mut reasons: Vec<(ErrorKind, usize)> = unreadable.into_iter().collect();
+                    reasons.sort_by(|(kind_a, a), (kind_b, b)| {
+                        b.cmp(a)
+                            .then_with(|| kind_a.to_string().cmp(&kind_b.to_string()))
+                    });
+                    reasons
+                        .iter()
+                        .map(|(kind, count)| format!("{}: {}", kind, count))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            };
+            warn!(
+                "{} could not be read, left out of the sizes ({})",
+                count(count_unreadable, "entry", "entries"),
+                reasons
+            );
+        }
+
+        if let Some(limit) = self.fail_over
+            && total.size as u64 > limit
+        {
+            let mut largest: Vec<&(PathBuf, Usage)> = items.iter().collect();
+            largest.sort_by_key(|(_, usage)| Reverse(usage.size));
+            let largest: Vec<String> = largest
+                .iter()
+                .take(3)
+                .map(|(path, usage)| {
+                    format!("{:?} ({})", path, human_size(usage.size as u64, self.si))
+                })
+                .collect();
+
+            let mut message = format!(
+                "Total size of {} exceeds the limit of {}",
+                human_size(total.size as u64, self.si),
+                human_size(limit, self.si)
+            );
+            if !largest.is_empty() {
+                message.push_str(&format!(", largest entries: {}", largest.join(", ")));
+            }
+            error!("{}", message);
+            return ExitCode::from(EXIT_OVER_LIMIT);
+        }
+
+        if partial && self.strict {
+            ExitCode::from(EXIT_PARTIAL)
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+
+    /// Disk usage of `path`, found `depth` levels below the analyzed directory when given a single one,
+    /// including the whole contents of directories, `root` being the analyzed directory it's found in
+    /// and `device` the filesystem of the directory containing it, which it must be on with `--one-file-system`.
+    ///
+    /// Entries at the shown depth are kept as they are scanned, as are files above it.
+    ///
+    /// Symlinks are counted themselves unless following them, in which case entries already `visited`
+    /// are counted as empty, breaking cycles and not counting entries reached through several paths twice.
+    /// Entries that can't be read are skipped with a warning.
+    fn disk_usage(
+        &self,
+        root: &Path,
+        path: &Path,
+        depth: usize,
+        device: Option<u64>,
+        scan: &Scan,
+    ) -> Usage {
+        if watch::interrupted() {
+            return Usage::default();
+        }
+
+        // the analyzed directories themselves are always followed
+        let metadata = if self.follow_symlinks || path == root {
+            metadata(path)
+        } else {
+            symlink_metadata(path)
+        };
+
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(_) if self.follow_symlinks && symlink_metadata(path).is_ok() => {
+                debug!("Counting broken symlink {:?} as empty", path);
+                return Usage::default();
+            }
+            Err(err) => {
+                warn!("Skipping {:?}: {}", path, err);
+                scan.unreadable(&err);
+                return Usage {
+                    partial: true,
+                    ..Default::default()
+                };
+            }
+        };
+
+        if self.one_file_system && device.is_some_and(|device| device != metadata.dev()) {
+            debug!("Skipping {:?}, on another filesystem", path);
+            return Usage::default();
+        }
+        // only mount points are skipped, so that pseudo filesystems are still scanned when analyzed explicitly
+        if device.is_some_and(|device| device != metadata.dev())
+            && self.virtual_devices.contains(&metadata.dev())
+        {
+            debug!("Skipping {:?}, a virtual filesystem", path);
+            return Usage::default();
+        }
+
+        if let Some(progress) = &scan.progress {
+            progress.found();
+        }
+
+        let apparent_size = metadata.len() as usize;
+        let disk_size = metadata.blocks() as usize * 512;
+        let size = if self.apparent_size {
+            apparent_size
+        } else {
+            disk_size
+        };
+
+        // files with several hard links are counted through the first of them reached
+        let deduplicate = if metadata.is_dir() {
+            self.follow_symlinks
+        } else {
+            !self.count_links && (self.follow_symlinks || metadata.nlink() > 1)
+        };
+        if deduplicate
+            && !scan
+                .visited
+                .lock()
+                .unwrap()
+                .insert((metadata.dev(), metadata.ino()))
+        {
+            debug!("Counting {:?} as empty, already counted", path);
+            return Usage {
+                deduplicated: if metadata.is_dir() { 0 } else { size },
+                ..Default::default()
+            };
+        }
+
+        let mut usage = Usage {
+            size,
+            apparent_size,
+            disk_size,
+            files: usize::from(!metadata.is_dir()),
+            dirs: usize::from(metadata.is_dir()),
+            modified: metadata.modified().ok(),
+            ..Default::default()
+        };
+        if deduplicate && self.cached.is_some() {
+            usage.linked.push((metadata.dev(), metadata.ino()));
+        }
+        if self.by_type {
+            self.keep_type(path, metadata.is_dir(), &usage, scan);
+        }
+        if self.by_owner {
+            self.keep_owner(metadata.uid(), &usage, scan);
+        }
+
+        if !metadata.is_dir() {
+            if self.files && metadata.is_file() {
+                self.keep_largest(path, &usage, scan);
+            }
+            if depth > 0 && depth <= self.depth && !self.dirs_only {
+                self.keep_entry(path, &usage, scan);
+            }
+            if depth > 0 {
+                self.keep_child(path, &usage);
+            }
+            return usage;
+        }
+
+        // directories at the shown depth would only be left out with `--files-only`
+        if self.files_only && depth > 0 && depth >= self.depth {
+            return usage;
+        }
+
+        if let Some(progress) = &scan.progress {
+            progress.reading(path);
+        }
+        let Listing {
+            paths,
+            hidden,
+            complete,
+            ..
+        } = self.listing(root, path, usage.modified, scan);
+        let children = paths.len() + hidden.len();
+
+        // directories whose entries aren't shown have nothing to keep but their usage
+        let cached = match (&self.cached, usage.modified) {
+            (Some(cached), Some(modified)) if depth >= self.depth => {
+                cached.get(path, modified, children)
+            }
+            _ => None,
+        };
+        if let Some(cached) = cached {
+            debug!("Reusing the cached size of {:?}", path);
+            scan.visited
+                .lock()
+                .unwrap()
+                .extend(cached.linked.iter().copied());
+            usage = cached;
+        } else {
+            let modified = usage.modified;
+            let contents = paths
+                .par_iter()
+                .map(|path| self.disk_usage(root, path, depth + 1, Some(metadata.dev()), scan))
+                .reduce(Usage::default, |mut contents, other| {
+                    contents.add(other);
+                    contents
+                });
+            usage.add(contents);
+            usage.hidden += hidden
+                .par_iter()
+                .map(|path| self.hidden_size(root, path, metadata.dev()))
+                .sum::<usize>();
+            usage.partial |= !complete;
+
+            // partial sizes are computed again, so that their errors are reported every time,
+            // and those of scans cut short when watching are left out
+            if let Some(cached) = &self.cached
+                && let Some(modified) = modified
+                && !usage.partial
+                && !watch::interrupted()
+            {
+                cached.insert(path, modified, children, &usage);
+            }
+        }
+
+        if depth > 0 && depth == self.depth && !self.files_only {
+            self.keep_entry(path, &usage, scan);
+        }
+        if depth > 0 {
+            self.keep_child(path, &usage);
+        }
+
+        usage
+    }
+
+    /// Entries of the directory at `path`, last modified at `modified`,
+    /// reusing those read at the same modification time by a previous scan when watching.
+    fn listing(
+        &self,
+        root: &Path,
+        path: &Path,
+        modified: Option<SystemTime>,
+        scan: &Scan,
+    ) -> Listing {
+        // entries are only added, removed or renamed along with the modification time of their directory
+        if let Some(listings) = &self.listings
+            && let Some(listing) = listings.lock().unwrap().get(path)
+            && modified.is_some()
+            && listing.modified == modified
+        {
+            return listing.clone();
+        }
+
+        let mut listing = Listing {
+            modified,
+            ..Default::default()
+        };
+        // directories that can't be read are still shown, with their own size
+        let entries = match path.read_dir() {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Skipping the contents of {:?}: {}", path, err);
+                scan.unreadable(&err);
+                return listing;
+            }
+        };
+
+        listing.complete = true;
+        for entry in entries {
+            match entry {
+                Ok(entry) if self.is_excluded(root, &entry.path()) => {}
+                Ok(entry)
+                    if self.no_hidden && entry.file_name().as_encoded_bytes().starts_with(b".") =>
+                {
+                    listing.hidden.push(entry.path())
+                }
+                Ok(entry) => listing.paths.push(entry.path()),
+                Err(err) => {
+                    warn!("Skipping an entry of {:?}: {}", path, err);
+                    scan.unreadable(&err);
+                    listing.complete = false;
+                }
+            }
+        }
+
+        // incomplete listings are read again, so that their errors are reported every time
+        if let Some(listings) = &self.listings
+            && listing.complete
+        {
+            listings
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), listing.clone());
+        }
+
+        listing
+    }
+
+    /// Size of the hidden entry at `path` and its whole contents, only tallied to be shown apart from the total.
+    ///
+    /// Symlinks are never followed, there being no record of visited entries to break cycles,
+    /// and entries that can't be read are left out silently.
+    fn hidden_size(&self, root: &Path, path: &Path, device: u64) -> usize {
+        let Ok(metadata) = symlink_metadata(path) else {
+            return 0;
+        };
+        if self.one_file_system && metadata.dev() != device {
+            return 0;
+        }
+
+        let size = if self.apparent_size {
+            metadata.len() as usize
+        } else {
+            metadata.blocks() as usize * 512
+        };
+        if !metadata.is_dir() {
+            return size;
+        }
+
+        let Ok(entries) = path.read_dir() else {
+            return size;
+        };
+        let paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| !self.is_excluded(root, path))
+            .collect();
+
+        size + paths
+            .par_iter()
+            .map(|path| self.hidden_size(root, path, metadata.dev()))
+            .sum::<usize>()
+    }
+
+    /// Keeps the entry at `path` among those shown
+    fn keep_entry(&self, path: &Path, usage: &Usage, scan: &Scan) {
+        scan.entries
+            .lock()
+            .unwrap()
+            .push((path.to_path_buf(), usage.clone()));
+    }
+
+    /// Keeps the entry at `path` among those of its directory, to be browsed with `--interactive`
+    fn keep_child(&self, path: &Path, usage: &Usage) {
+        if let Some(tree) = &self.tree
+            && let Some(parent) = path.parent()
+        {
+            tree.lock()
+                .unwrap()
+                .entry(parent.to_path_buf())
+                .or_default()
+                .push((path.to_path_buf(), usage.clone()));
+        }
+    }
+
+    /// Counts the entry at `path` in the usage of its extension, that of directories themselves being apart
+    fn keep_type(&self, path: &Path, is_dir: bool, usage: &Usage, scan: &Scan) {
+        let kind = match path.extension() {
+            _ if is_dir => "(directories)".to_string(),
+            Some(extension) => format!(".{}", extension.to_string_lossy().to_lowercase()),
+            None => "(no extension)".to_string(),
+        };
+
+        scan.types
+            .lock()
+            .unwrap()
+            .entry(kind)
+            .or_default()
+            .add(usage.clone());
+    }
+
+    /// Counts an entry in the usage of its owner
+    fn keep_owner(&self, uid: u32, usage: &Usage, scan: &Scan) {
+        scan.owners
+            .lock()
+            .unwrap()
+            .entry(uid)
+            .or_default()
+            .add(usage.clone());
+    }
+
+    /// Keeps the file at `path` among the largest ones found, only holding as many of them as shown
+    fn keep_largest(&self, path: &Path, usage: &Usage, scan: &Scan) {
+        let mut largest = scan.largest.lock().unwrap();
+        largest.push(Reverse((usage.size, path.to_path_buf(), usage.clone())));
+        if self.limit != 0 && largest.len() > self.limit {
+            largest.pop();
+        }
+    }
+
+    /// Whether the most recent modification found in an entry is within `--older-than` and `--newer-than`
+    fn matches_age(&self, usage: &Usage) -> bool {
+        if self.older_than.is_none() && self.newer_than.is_none() {
+            return true;
+        }
+
+        let Some(age) = usage
+            .modified
+            .map(|modified| modified.elapsed().unwrap_or_default())
+        else {
+            return false;
+        };
+
+        self.older_than.is_none_or(|older_than| age > older_than)
+            && self.newer_than.is_none_or(|newer_than| age < newer_than)
+    }
+
+    /// Whether an entry is left out by `--exclude`, along with its whole contents
+    fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+        self.excludes.as_ref().is_some_and(|excludes| {
+            let (filename, relative) = match_names(root, path);
+            excludes.is_match(filename, relative)
+        })
+    }
+
+    /// Sorts `items` by the chosen order, ties being broken by path so that the output is deterministic.
+    fn sort(&self, items: &mut [(PathBuf, Usage)]) {
+        items.sort_by(|(path_a, a), (path_b, b)| {
+            let ordering = match self.sort {
+                Sort::Size => b.size.cmp(&a.size),
+                Sort::Name => Ordering::Equal,
+                Sort::Count => b.files.cmp(&a.files),
+                Sort::Mtime => b.modified.cmp(&a.modified),
+            };
+            let ordering = if self.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+
+            ordering.then_with(|| {
+                let by_path = path_a.cmp(path_b);
+                if self.reverse && self.sort == Sort::Name {
+                    by_path.reverse()
+                } else {
+                    by_path
+                }
+            })
+        });
+    }
+}
+
+/// Name of the user with the given id, or the id itself for users that no longer exist
+fn owner_name(uid: u32) -> String {
+    match get_user_by_uid(uid) {
+        Some(user) => user.name().to_string_lossy().into_owned(),
+        None => uid.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs::{Permissions, create_dir, hard_link, remove_file, set_permissions, write};
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        hog: Hog,
+    }
+
+    fn hog(args: &[&str]) -> Hog {
+        Cli::parse_from(["hog"].iter().chain(args)).hog
+    }
+
+    /// Total usage of the directories analyzed by `hog`, scanned with `jobs` threads
+    fn total(hog: &Hog, jobs: usize) -> Usage {
+        let pool = ThreadPoolBuilder::new().num_threads(jobs).build().unwrap();
+        pool.install(|| hog.total(&Scan::default()))
+    }
+
+    /// Total usage as scanned with `args`, reusing and then updating the sizes cached in `cache`.
+    ///
+    /// A single thread scans the entries in order, several directories being scanned in the order given.
+    fn cached_total(args: &[&str], cache: &TempDir) -> Usage {
+        let mut hog = hog(args);
+        hog.cached = Some(Cache::load_from(
+            cache.path().join("hog.json"),
+            Options::of(&hog),
+        ));
+        let total = total(&hog, 1);
+        hog.cached.unwrap().save().unwrap();
+        total
+    }
+
+    fn dir(parent: &Path, name: &str) -> PathBuf {
+        let dir = parent.join(name);
+        create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn file(dir: &Path, name: &str, size: usize) -> PathBuf {
+        let path = dir.join(name);
+        write(&path, vec![b'x'; size]).unwrap();
+        path
+    }
+
+    fn arg(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn reuses_cached_sizes_of_unchanged_directories() {
+        let (root, cache) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let a = dir(root.path(), "a");
+        let sub = dir(&a, "sub");
+        let grown = file(&sub, "grown", 100);
+        let args = ["--apparent-size", arg(root.path())];
+
+        let first = cached_total(&args, &cache);
+        assert_eq!(first, total(&hog(&args), 1));
+
+        // growing a file changes neither the number of entries nor the modification time of its directory
+        write(&grown, vec![b'x'; 300]).unwrap();
+        assert_eq!(cached_total(&args, &cache), first);
+
+        // only the entries of a directory are checked, the cached size of `sub` still being reused
+        file(&a, "added", 50);
+        let recomputed = cached_total(&args, &cache);
+        assert_eq!(recomputed.size, first.size + 50);
+        assert_eq!(recomputed.files, 2);
+        assert_eq!(total(&hog(&args), 1).size, first.size + 200 + 50);
+    }
+
+    #[test]
+    fn reuses_cached_sizes_between_scans_of_the_same_run() {
+        let (root, cache) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let grown = file(&dir(root.path(), "sub"), "grown", 100);
+        let mut hog = hog(&["--apparent-size", "--cache", "--watch=1", arg(root.path())]);
+        let path = cache.path().join("hog.json");
+        hog.cached = Some(Cache::load_from(path.clone(), Options::of(&hog)));
+
+        let first = total(&hog, 1);
+        hog.cached.as_ref().unwrap().save().unwrap();
+        // as when watching, the sizes saved are reused without reading the cache file again
+        remove_file(&path).unwrap();
+        write(&grown, vec![b'x'; 300]).unwrap();
+        assert_eq!(total(&hog, 1), first);
+
+        file(root.path(), "added", 50);
+        assert_eq!(total(&hog, 1).size, first.size + 50);
+    }
+
+    #[test]
+    fn counts_hard_links_in_cached_directories_once() {
+        let (root, cache) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let (a, b) = (dir(root.path(), "a"), dir(root.path(), "b"));
+        hard_link(file(&a, "file", 1000), b.join("link")).unwrap();
+        let args = ["--apparent-size", arg(&a), arg(&b)];
+
+        let first = cached_total(&args, &cache);
+        assert_eq!(first.deduplicated, 1000);
+
+        // the size of `a` is reused, along with the file it counted, while `b` is scanned again
+        file(&b, "added", 10);
+        let second = cached_total(&args, &cache);
+        assert_eq!(second.size, first.size + 10);
+        assert_eq!(second.deduplicated, 1000);
+    }
+
+    #[test]
+    fn scans_the_same_with_any_number_of_threads() {
+        let root = TempDir::new().unwrap();
+        for i in 0..8 {
+            let sub = dir(&dir(root.path(), &format!("dir{i}")), "sub");
+            for j in 0..20 {
+                file(&sub, &format!("file{j}"), i * 100 + j);
+            }
+        }
+        // hard links crossing subdirectories, counted through whichever is reached first
+        for i in 0..8 {
+            let target = root.path().join(format!("dir{i}/sub/file{i}"));
+            hard_link(
+                &target,
+                root.path().join(format!("dir{}/link", (i + 1) % 8)),
+            )
+            .unwrap();
+            hard_link(
+                &target,
+                root.path().join(format!("dir{}/sub/link", (i + 3) % 8)),
+            )
+            .unwrap();
+        }
+
+        for args in [&["--apparent-size"][..], &[], &["--count-links"]] {
+            let mut args = args.to_vec();
+            args.push(arg(root.path()));
+            let hog = hog(&args);
+            let serial = total(&hog, 1);
+            for jobs in [2, 4, 16] {
+                for _ in 0..5 {
+                    assert_eq!(
+                        total(&hog, jobs),
+                        serial,
+                        "scanning with {jobs} threads and {args:?}"
+                    );
+                }
+            }
+        }
+
+        let total = total(&hog(&["--apparent-size", arg(root.path())]), 4);
+        assert_eq!((total.files, total.dirs), (8 * 20, 1 + 16));
+        assert_eq!(
+            total.deduplicated,
+            (0..8).map(|i| 2 * (i * 100 + i)).sum::<usize>()
+        );
+    }
+
+    /// Entries kept by a scan with `hog`, by path relative to `root`
+    fn entries(hog: &Hog, root: &Path) -> HashMap<String, Usage> {
+        let scan = Scan::default();
+        hog.total(&scan);
+        scan.entries
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|(path, usage)| {
+                let path = path.strip_prefix(root).unwrap();
+                (path.to_str().unwrap().to_string(), usage)
+            })
+            .collect()
+    }
+
+    fn len(path: &Path) -> usize {
+        metadata(path).unwrap().len() as usize
+    }
+
+    #[test]
+    fn sizes_directories_with_their_whole_contents() {
+        let root = TempDir::new().unwrap();
+        let a = dir(root.path(), "a");
+        file(&a, "x", 100);
+        let sub = dir(&a, "sub");
+        file(&sub, "y", 200);
+        let deeper = dir(&sub, "deeper");
+        file(&deeper, "z", 300);
+        file(root.path(), "b", 50);
+        let c_size = len(&dir(root.path(), "c"));
+
+        let hog = hog(&["--apparent-size", arg(root.path())]);
+        let shown = entries(&hog, root.path());
+        assert_eq!(shown.len(), 3);
+        let a_size = 600 + len(&a) + len(&sub) + len(&deeper);
+        let (a, b, c) = (&shown["a"], &shown["b"], &shown["c"]);
+        assert_eq!((a.size, a.files, a.dirs), (a_size, 3, 3));
+        assert_eq!((b.size, b.files, b.dirs), (50, 1, 0));
+        assert_eq!((c.size, c.files, c.dirs), (c_size, 0, 1));
+
+        let total = total(&hog, 1);
+        assert_eq!(total.size, a_size + 50 + c_size + len(root.path()));
+        assert_eq!((total.files, total.dirs), (4, 5));
+
+        // deeper entries are shown along with the files above them
+        let hog = self::hog(&["--apparent-size", "--depth", "2", arg(root.path())]);
+        let shown = entries(&hog, root.path());
+        let mut paths: Vec<&str> = shown.keys().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(paths, ["a/sub", "a/x", "b"]);
+        assert_eq!(shown["a/sub"].size, 500 + len(&sub) + len(&deeper));
+    }
+
+    /// Fabricated items, sized after their position
+    fn items(count: usize) -> Items {
+        (1..=count)
+            .map(|size| {
+                let usage = Usage {
+                    size,
+                    ..Default::default()
+                };
+                (PathBuf::from(format!("item{size}")), usage)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limits_the_shown_items() {
+        for (limit, count, shown) in [
+            ("0", 5, 5),
+            ("0", 0, 0),
+            ("1", 5, 1),
+            ("3", 5, 3),
+            ("5", 5, 5),
+            ("10", 5, 5),
+            ("1", 0, 0),
+        ] {
+            let (items, filtered) = hog(&["--limit", limit]).shown(items(count));
+            assert_eq!(
+                items.len(),
+                shown,
+                "showing {count} items with --limit {limit}"
+            );
+            assert!(filtered.is_empty());
+        }
+        assert_eq!(hog(&[]).shown(items(20)).0.len(), 10);
+
+        // the largest items are kept
+        let (items, _) = hog(&["--limit", "2"]).shown(items(5));
+        let sizes: Vec<usize> = items.iter().map(|(_, usage)| usage.size).collect();
+        assert_eq!(sizes, [5, 4]);
+    }
+
+    #[test]
+    fn totals_the_rest_of_a_tree_with_an_unreadable_directory() {
+        // root reads directories whatever their permissions
+        if uzers::get_current_uid() == 0 {
+            return;
+        }
+        let (root, expected) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        for root in [root.path(), expected.path()] {
+            file(root, "readable", 100);
+            dir(root, "locked");
+        }
+        let locked = root.path().join("locked");
+        file(&locked, "unreadable", 1000);
+        set_permissions(&locked, Permissions::from_mode(0o000)).unwrap();
+
+        let ctx = Context {
+            dry_run: false,
+            quiet: true,
+            color: crate::cli::Color::Off,
+            prompt: crate::cli::Prompt::No,
+            config: Default::default(),
+        };
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let scan = |args: &[&str]| {
+            let hog = hog(&[&["--apparent-size", arg(root.path())], args].concat());
+            let (usage, items, unreadable) = hog.scan(&pool, &ctx);
+            let count: usize = unreadable.values().sum();
+            let code = hog.finish(&usage, &items, unreadable);
+            (usage, count, code)
+        };
+        let (usage, count, code) = scan(&[]);
+        let (_, _, strict) = scan(&["--strict"]);
+        set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+
+        let readable = total(&hog(&["--apparent-size", arg(expected.path())]), 1);
+        assert_eq!((usage.size, usage.files), (readable.size, readable.files));
+        assert_eq!(count, 1);
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(strict, ExitCode::from(EXIT_PARTIAL));
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+/// Types of pseudo filesystems, whose entries don't use disk space but may report huge sizes, like `/proc/kcore`
+#[cfg(target_os = "linux")]
+const VIRTUAL_TYPES: [&str; 19] = [
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "proc",
+    "pstore",
+    "securityfs",
+    "sysfs",
+    "tracefs",
+];
+
+/// Devices of the pseudo filesystems mounted, as listed by `/proc/mounts`
+#[cfg(target_os = "linux")]
+pub(super) fn virtual_devices() -> HashSet<u64> {
+    use std::{fs::read_to_string, os::unix::fs::MetadataExt};
+    use tracing::debug;
+
+    let mounts = match read_to_string("/proc/mounts") {
+        Ok(mounts) => mounts,
+        Err(err) => {
+            debug!(
+                "Not skipping virtual filesystems, failed to read /proc/mounts: {}",
+                err
+            );
+            return HashSet::new();
+        }
+    };
+
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (_, mount_point, kind) = (fields.next()?, fields.next()?, fields.next()?);
+            VIRTUAL_TYPES.contains(&kind).then(|| unescape(mount_point))
+        })
+        .filter_map(|mount_point| std::fs::metadata(mount_point).ok())
+        .map(|metadata| metadata.dev())
+        .collect()
+}
+
+/// Devices of the pseudo filesystems mounted, only known on Linux
+#[cfg(not(target_os = "linux"))]
+pub(super) fn virtual_devices() -> HashSet<u64> {
+    HashSet::new()
+}
+
+/// Mount point as written in `/proc/mounts`, where spaces, tabs, newlines and backslashes are octal escapes
+#[cfg(target_os = "linux")]
+fn unescape(mount_point: &str) -> String {
+    let mut unescaped = String::with_capacity(mount_point.len());
+    let mut rest = mount_point;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let escape = rest.get(index + 1..index + 4);
+        match escape.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
@@ -0,0 +1,62 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    time::Duration,
+};
+use terminal_size::{Width, terminal_size};
+
+use super::report::ellipsize;
+use crate::utils::logging::{clear_status, show_status};
+
+/// Time between two updates of the progress line
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Progress of a scan, shown on a status line of stderr while it runs
+#[derive(Debug, Default)]
+pub(super) struct Progress {
+    /// Number of entries found so far
+    entries: AtomicUsize,
+    /// Directory being read most recently
+    current: Mutex<PathBuf>,
+}
+
+impl Progress {
+    /// Counts an entry found while scanning
+    pub(super) fn found(&self) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the directory being read, unless another thread is doing so at the same time
+    pub(super) fn reading(&self, dir: &Path) {
+        if let Ok(mut current) = self.current.try_lock() {
+            dir.clone_into(&mut current);
+        }
+    }
+
+    /// Draws the progress line until `stopped` is disconnected, then clears it.
+    pub(super) fn draw(&self, stopped: Receiver<()>) {
+        const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+        let mut frame = 0;
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+            let width = terminal_size().map_or(80, |(Width(width), _)| width as usize);
+            let status = format!(
+                "{} Scanned {} entries, in ",
+                SPINNER[frame % SPINNER.len()],
+                self.entries.load(Ordering::Relaxed)
+            );
+            let current = self.current.lock().unwrap().to_string_lossy().into_owned();
+            // the last column is left empty so that terminals don't wrap the line
+            let current_width = width.saturating_sub(status.chars().count() + 1);
+
+            show_status(&format!("{}{}", status, ellipsize(&current, current_width)));
+            frame += 1;
+        }
+
+        clear_status();
+    }
+}
@@ -0,0 +1,428 @@
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write, stdout},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+use terminal_size::{Width, terminal_size};
+
+use super::{
+    Hog, Usage,
+    snapshot::{Change, Snapshot},
+};
+use crate::utils::size::human_size;
+use crate::utils::style::Style;
+
+/// Size from which entries are highlighted in text output, 1 GiB
+const LARGE: usize = 1 << 30;
+
+/// Format used to print the analyzed items
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    /// `path: size` lines following the total size
+    #[default]
+    Text,
+    /// A JSON object with the total size and the shown items
+    Json,
+    /// A header followed by one row per shown item, sizes in bytes
+    Csv,
+    /// NUL terminated paths of the shown items, without sizes, written as raw bytes
+    Nul,
+}
+
+/// Analyzed items as printed in JSON
+#[derive(Debug, Serialize)]
+struct Summary {
+    total_bytes: usize,
+    total_apparent_bytes: usize,
+    total_disk_bytes: usize,
+    deduplicated_bytes: usize,
+    hidden_bytes: usize,
+    filtered_bytes: usize,
+    filtered_entries: usize,
+    entries: Vec<Entry>,
+}
+
+/// A single analyzed item as printed in JSON
+#[derive(Debug, Serialize)]
+struct Entry {
+    path: String,
+    bytes: usize,
+    apparent_bytes: usize,
+    disk_bytes: usize,
+    human: String,
+    percent: f64,
+    cumulative_percent: f64,
+    files: usize,
+    dirs: usize,
+    /// Most recent modification time found, in seconds since the Unix epoch
+    newest_mtime: Option<u64>,
+}
+
+/// Changes since a snapshot as printed in JSON
+#[derive(Debug, Serialize)]
+struct DiffSummary {
+    total_bytes: usize,
+    previous_total_bytes: usize,
+    grown_bytes: u128,
+    shrunk_bytes: u128,
+    entries: Vec<DiffEntry>,
+}
+
+/// Change of a single entry as printed in JSON
+#[derive(Debug, Serialize)]
+struct DiffEntry {
+    path: String,
+    bytes: Option<usize>,
+    previous_bytes: Option<usize>,
+    delta_bytes: i128,
+    status: &'static str,
+}
+
+impl Output {
+    /// Prints the `total` usage of the analyzed directory and the shown `items` to stdout,
+    /// as chosen by the options of `hog`, along with the size of the items `filtered` out by age.
+    ///
+    /// Items found in `changes` are highlighted in text along with their change in size,
+    /// as when watching.
+    pub(super) fn print(
+        self,
+        hog: &Hog,
+        total: &Usage,
+        items: &[(PathBuf, Usage)],
+        filtered: &[(PathBuf, Usage)],
+        changes: &HashMap<String, i128>,
+    ) -> Result<()> {
+        let size = |size: usize| {
+            if hog.human_readable {
+                human_size(size as u64, hog.si)
+            } else {
+                size.to_string()
+            }
+        };
+
+        // share of the total of each item, and running share in the shown order
+        let percent = |size: usize| match total.size {
+            0 => 0.0,
+            total => size as f64 * 100.0 / total as f64,
+        };
+        let percents: Vec<(f64, f64)> = items
+            .iter()
+            .scan(0, |shown, (_, usage)| {
+                *shown += usage.size;
+                Some((percent(usage.size), percent(*shown)))
+            })
+            .collect();
+
+        let filtered_size: usize = filtered.iter().map(|(_, usage)| usage.size).sum();
+
+        match self {
+            // `size<TAB>path` lines followed by the total with several directories, like `du -sc`
+            Output::Text if hog.summarize => {
+                for (path, usage) in items {
+                    println!("{}\t{}", size(usage.size), path.to_string_lossy());
+                }
+                if items.len() > 1 {
+                    println!("{}\ttotal", size(total.size));
+                }
+            }
+            Output::Text => {
+                println!("Total size: {}", size(total.size));
+                if total.deduplicated > 0 {
+                    println!("Duplicate links not counted: {}", size(total.deduplicated));
+                }
+                if total.hidden > 0 {
+                    println!("Hidden entries left out: {}", size(total.hidden));
+                }
+                if !filtered.is_empty() {
+                    println!(
+                        "Filtered out by age: {} in {}",
+                        size(filtered_size),
+                        count(filtered.len(), "entry", "entries")
+                    );
+                }
+                // bars are only meant for the terminal
+                let graph = hog.graph && stdout().is_terminal();
+                let width = terminal_size().map_or(80, |(Width(width), _)| width as usize);
+                let bar_width = (width / 4).max(10);
+                let largest = items.iter().map(|(_, usage)| usage.size).max().unwrap_or(0);
+
+                for ((path, usage), (percent, cumulative)) in items.iter().zip(&percents) {
+                    let path = path.to_string_lossy();
+                    let shown_size = size(usage.size);
+                    let shares = format!(
+                        "({:.1}%, {}, {})",
+                        if hog.cumulative { cumulative } else { percent },
+                        count(usage.files, "file", "files"),
+                        count(usage.dirs, "dir", "dirs")
+                    );
+                    let change = changes.get(path.as_ref()).map(|&delta| {
+                        let sign = if delta < 0 { '-' } else { '+' };
+                        format!(" {}{}", sign, size(delta.unsigned_abs() as usize))
+                    });
+                    // widths are those of the text without styles
+                    let details_width = shown_size.chars().count()
+                        + shares.chars().count()
+                        + change.as_ref().map_or(0, |change| change.chars().count())
+                        + 3;
+
+                    let styler = hog.styler;
+                    let mut details = format!(
+                        ": {} {}",
+                        if usage.size >= LARGE {
+                            styler.paint(&shown_size, Style::Red)
+                        } else {
+                            shown_size
+                        },
+                        styler.paint(&shares, Style::Dim)
+                    );
+                    if let Some(change) = &change {
+                        details.push_str(&styler.paint(change, Style::Yellow));
+                    }
+                    let paint_path = |path: &str| {
+                        // groups by extension or owner aren't directories themselves
+                        if usage.dirs > 0 && !hog.by_type && !hog.by_owner {
+                            styler.paint(path, Style::BoldBlue)
+                        } else {
+                            path.to_string()
+                        }
+                    };
+
+                    if graph {
+                        let fraction = match largest {
+                            0 => 0.0,
+                            largest => usage.size as f64 / largest as f64,
+                        };
+                        let path_width = width.saturating_sub(bar_width + 3 + details_width);
+                        println!(
+                            "[{}] {}{}",
+                            bar(fraction, bar_width),
+                            paint_path(&ellipsize(&path, path_width)),
+                            details
+                        );
+                    } else {
+                        println!("{}{}", paint_path(&path), details);
+                    }
+                }
+            }
+            Output::Json => {
+                let summary = Summary {
+                    total_bytes: total.size,
+                    total_apparent_bytes: total.apparent_size,
+                    total_disk_bytes: total.disk_size,
+                    deduplicated_bytes: total.deduplicated,
+                    hidden_bytes: total.hidden,
+                    filtered_bytes: filtered_size,
+                    filtered_entries: filtered.len(),
+                    entries: items
+                        .iter()
+                        .zip(&percents)
+                        .map(|((path, usage), &(percent, cumulative_percent))| Entry {
+                            path: path.to_string_lossy().into_owned(),
+                            bytes: usage.size,
+                            apparent_bytes: usage.apparent_size,
+                            disk_bytes: usage.disk_size,
+                            human: human_size(usage.size as u64, hog.si),
+                            percent,
+                            cumulative_percent,
+                            files: usage.files,
+                            dirs: usage.dirs,
+                            newest_mtime: usage
+                                .modified
+                                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                                .map(|modified| modified.as_secs()),
+                        })
+                        .collect(),
+                };
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            }
+            Output::Csv => {
+                println!("path,bytes,apparent_bytes,disk_bytes,files,dirs");
+                for (path, usage) in items {
+                    println!(
+                        "{},{},{},{},{},{}",
+                        csv_field(&path.to_string_lossy()),
+                        usage.size,
+                        usage.apparent_size,
+                        usage.disk_size,
+                        usage.files,
+                        usage.dirs
+                    );
+                }
+            }
+            Output::Nul => {
+                let mut out = stdout().lock();
+                for (path, _) in items {
+                    out.write_all(path.as_os_str().as_bytes())?;
+                    out.write_all(b"\0")?;
+                }
+                out.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints the `changes` of the shown entries since the `previous` snapshot, along with the `total` usage
+    /// of the analyzed directory, to stdout.
+    pub(super) fn print_diff(
+        self,
+        hog: &Hog,
+        total: &Usage,
+        previous: &Snapshot,
+        changes: &[Change],
+    ) -> Result<()> {
+        let size = |size: u128| {
+            if hog.human_readable {
+                human_size(size as u64, hog.si)
+            } else {
+                size.to_string()
+            }
+        };
+        let delta = |delta: i128| {
+            let sign = if delta < 0 { '-' } else { '+' };
+            format!("{}{}", sign, size(delta.unsigned_abs()))
+        };
+        let status = |change: &Change| match (change.bytes, change.previous_bytes) {
+            (_, None) => "new",
+            (None, _) => "removed",
+            _ => "changed",
+        };
+
+        let grown: u128 = changes
+            .iter()
+            .filter(|change| change.delta() > 0)
+            .map(|change| change.delta().unsigned_abs())
+            .sum();
+        let shrunk: u128 = changes
+            .iter()
+            .filter(|change| change.delta() < 0)
+            .map(|change| change.delta().unsigned_abs())
+            .sum();
+
+        match self {
+            Output::Text => {
+                println!(
+                    "Total size: {} ({})",
+                    size(total.size as u128),
+                    delta(total.size as i128 - previous.total_bytes() as i128)
+                );
+                println!("Grown: +{}, shrunk: -{}", size(grown), size(shrunk));
+
+                for change in changes {
+                    let path = match status(change) {
+                        "changed" => change.path.clone(),
+                        status => format!("{} ({})", change.path, status),
+                    };
+                    println!("{}: {}", path, delta(change.delta()));
+                }
+            }
+            Output::Json => {
+                let summary = DiffSummary {
+                    total_bytes: total.size,
+                    previous_total_bytes: previous.total_bytes(),
+                    grown_bytes: grown,
+                    shrunk_bytes: shrunk,
+                    entries: changes
+                        .iter()
+                        .map(|change| DiffEntry {
+                            path: change.path.clone(),
+                            bytes: change.bytes,
+                            previous_bytes: change.previous_bytes,
+                            delta_bytes: change.delta(),
+                            status: status(change),
+                        })
+                        .collect(),
+                };
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            }
+            Output::Csv => {
+                println!("path,bytes,previous_bytes,delta_bytes,status");
+                for change in changes {
+                    println!(
+                        "{},{},{},{},{}",
+                        csv_field(&change.path),
+                        change
+                            .bytes
+                            .map_or(String::new(), |bytes| bytes.to_string()),
+                        change
+                            .previous_bytes
+                            .map_or(String::new(), |bytes| bytes.to_string()),
+                        change.delta(),
+                        status(change)
+                    );
+                }
+            }
+            Output::Nul => {
+                let mut out = stdout().lock();
+                for change in changes {
+                    out.write_all(change.path.as_bytes())?;
+                    out.write_all(b"\0")?;
+                }
+                out.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Count of things shortened to thousands and millions, such as `1 file` or `480k files`
+pub(super) fn count(count: usize, singular: &str, plural: &str) -> String {
+    let count_text = match count {
+        0..1_000 => count.to_string(),
+        1_000..10_000 => format!("{:.1}k", count as f64 / 1e3),
+        10_000..1_000_000 => format!("{}k", count / 1_000),
+        1_000_000..10_000_000 => format!("{:.1}M", count as f64 / 1e6),
+        _ => format!("{}M", count / 1_000_000),
+    };
+
+    format!(
+        "{} {}",
+        count_text,
+        if count == 1 { singular } else { plural }
+    )
+}
+
+/// Bar of `width` characters filled up to `fraction`, in eighths of a character
+fn bar(fraction: f64, width: usize) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+    let eighths = (fraction.clamp(0.0, 1.0) * (width * 8) as f64).round() as usize;
+    let mut bar = "█".repeat(eighths / 8);
+    if !eighths.is_multiple_of(8) {
+        bar.push(PARTIAL[eighths % 8]);
+    }
+
+    let filled = bar.chars().count();
+    bar + &" ".repeat(width - filled)
+}
+
+/// Shortens `text` to `width` characters by replacing its middle with an ellipsis
+pub(super) fn ellipsize(text: &str, width: usize) -> String {
+    let length = text.chars().count();
+    if length <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let kept = width - 1;
+    let head: String = text.chars().take(kept.div_ceil(2)).collect();
+    let tail: String = text.chars().skip(length - kept / 2).collect();
+    format!("{}…{}", head, tail)
+}
+
+/// Quotes a CSV field if it contains a delimiter, a quote or a line break, doubling its quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
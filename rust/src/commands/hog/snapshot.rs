@@ -0,0 +1,154 @@
+use chrono::Local;
+use color_eyre::eyre::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use super::Usage;
+
+/// Version of the snapshot format, to be increased whenever it changes incompatibly
+const VERSION: u32 = 1;
+
+/// Sizes of the entries of a scan, saved to be compared against later ones
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Snapshot {
+    version: u32,
+    /// Time of the scan, in RFC 3339 format
+    created: String,
+    /// Analyzed directories
+    dirs: Vec<String>,
+    total_bytes: usize,
+    entries: Vec<Entry>,
+}
+
+/// Size of a single entry of a snapshot
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    path: String,
+    bytes: usize,
+}
+
+/// Only the version of a snapshot, read before the rest of it
+#[derive(Debug, Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+/// Difference in size of an entry between a snapshot and the current scan
+#[derive(Debug)]
+pub(super) struct Change {
+    pub path: String,
+    /// Current size, `None` if the entry was removed since the snapshot
+    pub bytes: Option<usize>,
+    /// Size in the snapshot, `None` if the entry is new
+    pub previous_bytes: Option<usize>,
+}
+
+impl Change {
+    /// Growth of the entry in bytes, negative if it shrank
+    pub fn delta(&self) -> i128 {
+        self.bytes.unwrap_or(0) as i128 - self.previous_bytes.unwrap_or(0) as i128
+    }
+}
+
+impl Snapshot {
+    /// Snapshot of the scan of `dirs` finding `items`, adding up to `total`
+    pub fn new(dirs: &[PathBuf], total: &Usage, items: &[(PathBuf, Usage)]) -> Self {
+        let mut entries: Vec<Entry> = items
+            .iter()
+            .map(|(path, usage)| Entry {
+                path: path.to_string_lossy().into_owned(),
+                bytes: usage.size,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self {
+            version: VERSION,
+            created: Local::now().to_rfc3339(),
+            dirs: dirs
+                .iter()
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .collect(),
+            total_bytes: total.size,
+            entries,
+        }
+    }
+
+    /// Total size of the scan
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Writes the snapshot to `path`, replacing any file already there
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Err(err) = write(path, serde_json::to_string_pretty(self)? + "\n") {
+            bail!("Failed to write snapshot {:?}: {}", path, err);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a snapshot, refusing those of another version of the format instead of misreading them
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => bail!("Failed to read snapshot {:?}: {}", path, err),
+        };
+
+        match serde_json::from_str::<Versioned>(&contents) {
+            Ok(Versioned { version: VERSION }) => {}
+            Ok(Versioned { version }) => bail!(
+                "Unsupported snapshot version {} in {:?}, expected {}",
+                version,
+                path,
+                VERSION
+            ),
+            Err(err) => bail!("Failed to parse snapshot {:?}: {}", path, err),
+        }
+
+        match serde_json::from_str(&contents) {
+            Ok(snapshot) => Ok(snapshot),
+            Err(err) => bail!("Failed to parse snapshot {:?}: {}", path, err),
+        }
+    }
+
+    /// Changes of the entries found in `items` since the snapshot, unchanged ones being left out,
+    /// largest growth or shrinkage first.
+    pub fn diff(&self, items: &[(PathBuf, Usage)]) -> Vec<Change> {
+        let mut previous: HashMap<&str, usize> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.bytes))
+            .collect();
+
+        let mut changes: Vec<Change> = items
+            .iter()
+            .map(|(path, usage)| {
+                let path = path.to_string_lossy().into_owned();
+                Change {
+                    previous_bytes: previous.remove(path.as_str()),
+                    bytes: Some(usage.size),
+                    path,
+                }
+            })
+            .collect();
+        changes.extend(previous.into_iter().map(|(path, bytes)| Change {
+            path: path.to_string(),
+            bytes: None,
+            previous_bytes: Some(bytes),
+        }));
+
+        changes.retain(|change| change.bytes != change.previous_bytes);
+        changes.sort_by(|a, b| {
+            b.delta()
+                .unsigned_abs()
+                .cmp(&a.delta().unsigned_abs())
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        changes
+    }
+}
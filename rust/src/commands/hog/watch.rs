@@ -0,0 +1,81 @@
+use color_eyre::eyre::Result;
+use console::Term;
+use rayon::ThreadPool;
+use std::{
+    collections::HashMap,
+    io::{Write, stdout},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::Duration,
+};
+
+use super::{Hog, browse::Screen, snapshot::Snapshot};
+use crate::cli::Context;
+
+/// Exit code of a watch interrupted with Ctrl-C, as the shell gives processes killed by `SIGINT`
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Whether watching was interrupted, the scan in progress being cut short
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether watching was interrupted with Ctrl-C
+pub(super) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+impl Hog {
+    /// Scans the analyzed directories every `interval` and redraws the listing on the alternate screen
+    /// of the terminal, until interrupted with Ctrl-C, which gives back the screen as it was.
+    pub(super) fn watch(
+        &self,
+        pool: &ThreadPool,
+        ctx: &Context,
+        interval: Duration,
+    ) -> Result<ExitCode> {
+        let (interrupt, interrupts) = mpsc::channel();
+        ctrlc::set_handler(move || {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            let _ = interrupt.send(());
+        })?;
+
+        // given back as it was whichever way watching ends
+        let _screen = Screen::enter(Term::stdout())?;
+        let mut out = stdout();
+
+        let mut previous: Option<Snapshot> = None;
+        loop {
+            let (total, items, unreadable) = self.scan(pool, ctx);
+            if interrupted() {
+                break;
+            }
+
+            let changes: HashMap<String, i128> = previous
+                .map(|previous| previous.diff(&items))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|change| {
+                    let delta = change.delta();
+                    (change.path, delta)
+                })
+                .collect();
+            previous = Some(Snapshot::new(&self.dirs, &total, &items));
+
+            let (items, filtered) = self.shown(items);
+            write!(out, "\x1b[H\x1b[2J")?;
+            out.flush()?;
+            self.output
+                .print(self, &total, &items, &filtered, &changes)?;
+            self.finish(&total, &items, unreadable);
+
+            match interrupts.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        }
+
+        Ok(ExitCode::from(EXIT_INTERRUPTED))
+    }
+}
@@ -1,29 +1,513 @@
-use clap::Args;
-use color_eyre::eyre::{bail, Result};
-use copypasta::{ClipboardContext, ClipboardProvider};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{Local, SecondsFormat};
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{Result, bail};
+use std::fs::write;
+use std::io::{ErrorKind, IsTerminal, Write, stdout};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tracing::{debug, error};
 
-use crate::cli::Runnable;
+use crate::cli::{Context, Runnable};
+use crate::utils::clipboard::{Backend, Clipboard, LineEnding, Serving, System};
+
+/// Exit code of pastes that found the clipboard empty
+const EXIT_EMPTY: u8 = 10;
+
+/// Exit code of pastes that found no clipboard to paste from, as in headless sessions
+const EXIT_UNAVAILABLE: u8 = 11;
+
+/// Exit code of watches interrupted with Ctrl-C, as the shell gives processes killed by `SIGINT`
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Contents pasted when the clipboard holds both text and an image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Prefer {
+    #[default]
+    Text,
+    Image,
+}
 
 #[derive(Args, Debug)]
-pub struct Paste {}
+pub struct Paste {
+    /// Clipboard to paste from
+    #[arg(long, value_enum, default_value_t = Backend::Auto)]
+    pub backend: Backend,
+
+    /// Give up on a backend not responding within the duration, such as an X server that stopped, trying the next one
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "5s")]
+    pub timeout: Duration,
+
+    /// Write the contents exactly as copied, without ending them with a newline on a terminal
+    #[arg(long)]
+    pub no_newline: bool,
+
+    /// Save the image on the clipboard as PNG to the file, or to stdout with `-`, failing if there is none
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["no_newline", "prefer"])]
+    pub image: Option<PathBuf>,
+
+    /// Contents written to stdout when both text and an image were copied
+    #[arg(long, value_enum, default_value_t = Prefer::Text)]
+    pub prefer: Prefer,
+
+    /// List the MIME types the clipboard contents are available as, such as `text/html`
+    #[arg(long, conflicts_with_all = ["no_newline", "image", "prefer", "target", "decode_base64"])]
+    pub targets: bool,
+
+    /// Write the clipboard contents as the MIME type, exactly as provided
+    #[arg(long, value_name = "MIME", conflicts_with_all = ["no_newline", "image", "prefer"])]
+    pub target: Option<String>,
+
+    /// Text written instead of the clipboard contents when the clipboard is empty or unavailable
+    #[arg(long, value_name = "TEXT", conflicts_with = "allow_empty")]
+    pub or_else: Option<String>,
+
+    /// Succeed with an empty clipboard, writing nothing
+    #[arg(long)]
+    pub allow_empty: bool,
+
+    /// Write the contents decoded from base64, as copied with `copy --base64`
+    #[arg(long, conflicts_with_all = ["no_newline", "image", "prefer", "target"])]
+    pub decode_base64: bool,
+
+    /// Convert line endings of the text to CRLF, as Windows uses
+    #[arg(long, conflicts_with_all = ["lf", "image", "targets", "target", "decode_base64"])]
+    pub crlf: bool,
+
+    /// Convert line endings of the text to LF, as Unix systems use
+    #[arg(long, conflicts_with_all = ["image", "targets", "target", "decode_base64"])]
+    pub lf: bool,
+
+    /// Write every new text copied, as a record, until interrupted with Ctrl-C
+    #[arg(
+        long,
+        conflicts_with_all = ["no_newline", "image", "prefer", "targets", "target", "or_else", "allow_empty", "decode_base64"]
+    )]
+    pub watch: bool,
+
+    /// Time between checks of the clipboard with `--watch`, such as `200ms`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "500ms", requires = "watch")]
+    pub interval: Duration,
+
+    /// Text ending every record written with `--watch` [default: newline]
+    #[arg(
+        long,
+        value_name = "TEXT",
+        default_value = "\n",
+        hide_default_value = true,
+        requires = "watch"
+    )]
+    pub delimiter: String,
+
+    /// End records with NUL instead, for `xargs -0`
+    #[arg(long, short = '0', conflicts_with = "delimiter", requires = "watch")]
+    pub print0: bool,
+
+    /// Start every record with the time it was copied at, followed by a tab
+    #[arg(long, requires = "watch")]
+    pub timestamps: bool,
+
+    /// Command to run with the contents as its stdin, after `--`, exiting with its status
+    #[arg(last = true, value_name = "COMMAND", conflicts_with_all = ["targets", "watch"])]
+    pub command: Vec<String>,
+}
+
+/// What a paste writes, unless it found nothing to
+#[derive(Debug, PartialEq, Eq)]
+enum Pasted {
+    /// Contents written, followed by a newline on a terminal if `newline` is set
+    Contents { data: Vec<u8>, newline: bool },
+    /// Nothing written, exiting with the code given
+    Nothing(u8),
+}
 
 impl Runnable for Paste {
-    fn run(&mut self) -> Result<()> {
-        let mut ctx = match ClipboardContext::new() {
-            Ok(ctx) => ctx,
-            Err(err) => {
-                bail!("Failed to create clipboard context: {}", err);
+    fn run(&mut self, _ctx: &Context) -> Result<ExitCode> {
+        let clipboard = System::new(self.backend, Serving::default(), self.timeout);
+        if self.watch {
+            return self.watch(&clipboard);
+        }
+
+        match self.paste(&clipboard)? {
+            Pasted::Contents { data, .. }
+                if let Some(path) = &self.image
+                    && path.as_os_str() != "-"
+                    && self.command.is_empty() =>
+            {
+                if let Err(err) = write(path, data) {
+                    bail!("Failed to write {:?}: {}", path, err);
+                }
+                Ok(ExitCode::SUCCESS)
             }
+            Pasted::Contents { data, newline } => self.output(&data, newline),
+            Pasted::Nothing(code) => Ok(ExitCode::from(code)),
+        }
+    }
+}
+
+impl Paste {
+    /// Contents of `clipboard` to write, as asked for
+    fn paste(&self, clipboard: &dyn Clipboard) -> Result<Pasted> {
+        let verbatim = |data| Pasted::Contents {
+            data,
+            newline: false,
+        };
+
+        if self.targets {
+            let mut data = Vec::new();
+            for target in clipboard.get_targets()? {
+                writeln!(data, "{}", target)?;
+            }
+            return Ok(verbatim(data));
+        }
+
+        if let Some(target) = &self.target {
+            let Some(contents) = clipboard.get_target(target)? else {
+                bail!("Clipboard contents aren't available as {}", target);
+            };
+            return Ok(verbatim(contents));
+        }
+
+        if self.image.is_some() {
+            let Some(image) = clipboard.get_image()? else {
+                bail!("Clipboard does not contain an image");
+            };
+            return Ok(verbatim(image));
+        }
+
+        if self.prefer == Prefer::Image
+            && let Some(image) = clipboard.get_image()?
+        {
+            return Ok(verbatim(image));
+        }
+
+        // scripts would otherwise take an empty clipboard for empty contents
+        let contents = match clipboard.get_text() {
+            Ok(contents) if !contents.is_empty() || self.allow_empty => contents,
+            result => match (&self.or_else, result) {
+                (Some(text), _) => text.clone().into_bytes(),
+                (None, Ok(_)) => {
+                    error!("Clipboard is empty");
+                    return Ok(Pasted::Nothing(EXIT_EMPTY));
+                }
+                (None, Err(err)) => {
+                    error!("{}", err);
+                    return Ok(Pasted::Nothing(EXIT_UNAVAILABLE));
+                }
+            },
         };
 
-        let contents = match ctx.get_contents() {
-            Ok(contents) => contents,
-            Err(err) => {
-                bail!("Failed to get clipboard contents: {}", err);
+        if self.decode_base64 {
+            // encoded contents may be wrapped into lines on their way
+            let encoded: Vec<u8> = contents
+                .into_iter()
+                .filter(|byte| !byte.is_ascii_whitespace())
+                .collect();
+            let decoded = match STANDARD.decode(encoded) {
+                Ok(decoded) => decoded,
+                Err(err) => bail!("Clipboard contents aren't valid base64: {}", err),
+            };
+            return Ok(verbatim(decoded));
+        }
+
+        let data = match self.line_ending() {
+            Some(ending) => ending.convert(&contents),
+            None => contents,
+        };
+        Ok(Pasted::Contents {
+            data,
+            newline: !self.no_newline,
+        })
+    }
+
+    /// Line endings the text is converted to, if any
+    fn line_ending(&self) -> Option<LineEnding> {
+        match (self.crlf, self.lf) {
+            (true, _) => Some(LineEnding::Crlf),
+            (_, true) => Some(LineEnding::Lf),
+            _ => None,
+        }
+    }
+
+    /// Writes the contents to stdout, followed by a newline on a terminal if `newline`, or to the stdin of the
+    /// command given after `--`, exactly as copied like to any pipe, exiting with its status.
+    fn output(&self, contents: &[u8], newline: bool) -> Result<ExitCode> {
+        let Some((program, args)) = self.command.split_first() else {
+            // only terminals get a newline, so that the prompt isn't left after the contents,
+            // whereas pipes and files get them verbatim
+            let mut out = stdout().lock();
+            out.write_all(contents)?;
+            if newline && out.is_terminal() {
+                out.write_all(b"\n")?;
             }
+            out.flush()?;
+            return Ok(ExitCode::SUCCESS);
+        };
+
+        let mut child = match Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => bail!("Failed to run {}: {}", program, err),
         };
+        if let Some(mut stdin) = child.stdin.take()
+            // commands may well exit without reading all of their input, as `head` does
+            && let Err(err) = stdin.write_all(contents)
+            && err.kind() != ErrorKind::BrokenPipe
+        {
+            bail!("Failed to write to {}: {}", program, err);
+        }
+
+        let status = child.wait()?;
+        Ok(ExitCode::from(exit_code(status)))
+    }
+
+    /// Checks the clipboard every `--interval`, writing its contents whenever they are new text, which the contents
+    /// found at first aren't, until interrupted with Ctrl-C.
+    ///
+    /// Failing checks are skipped, as a clipboard left without an owner may be for a moment, but not the first one.
+    fn watch(&self, clipboard: &dyn Clipboard) -> Result<ExitCode> {
+        let (interrupt, interrupts) = mpsc::channel();
+        ctrlc::set_handler(move || {
+            let _ = interrupt.send(());
+        })?;
+
+        let delimiter = if self.print0 { "\0" } else { &self.delimiter };
+        let mut previous = clipboard.get_text()?;
+        let mut out = stdout().lock();
+        loop {
+            match interrupts.recv_timeout(self.interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return Ok(ExitCode::from(EXIT_INTERRUPTED)),
+            }
+
+            let contents = match clipboard.get_text() {
+                Ok(contents) => contents,
+                Err(err) => {
+                    debug!("Failed to check the clipboard: {}", err);
+                    continue;
+                }
+            };
+            // consecutive copies of the same text are a single record
+            if contents.is_empty() || contents == previous {
+                continue;
+            }
+
+            if self.timestamps {
+                write!(
+                    out,
+                    "{}\t",
+                    Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)
+                )?;
+            }
+            match self.line_ending() {
+                Some(ending) => out.write_all(&ending.convert(&contents))?,
+                None => out.write_all(&contents)?,
+            }
+            out.write_all(delimiter.as_bytes())?;
+            out.flush()?;
+            previous = contents;
+        }
+    }
+}
+
+/// Exit code reflecting how a command exited, as the shell gives it, `128` and the signal for commands killed by one
+fn exit_code(status: ExitStatus) -> u8 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128u8.saturating_add(signal as u8);
+        }
+    }
+
+    status.code().unwrap_or(1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::copy::Copy;
+    use crate::utils::clipboard::{Contents, Mock};
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        paste: Paste,
+    }
+
+    #[derive(Parser)]
+    struct CopyCli {
+        #[command(flatten)]
+        copy: Copy,
+    }
+
+    /// Paste with the given arguments, as given on the command line
+    fn paste(args: &[&str]) -> Paste {
+        Cli::parse_from(["paste"].iter().chain(args)).paste
+    }
+
+    /// Clipboard holding `text`
+    fn text(text: &str) -> Mock {
+        Mock::with(Contents::Text(text.to_string()))
+    }
+
+    /// Contents written verbatim
+    fn verbatim(data: &[u8]) -> Pasted {
+        Pasted::Contents {
+            data: data.to_vec(),
+            newline: false,
+        }
+    }
+
+    #[test]
+    fn pastes_text() {
+        assert_eq!(
+            paste(&[]).paste(&text("text")).unwrap(),
+            Pasted::Contents {
+                data: b"text".to_vec(),
+                newline: true,
+            }
+        );
+        assert_eq!(
+            paste(&["--no-newline"]).paste(&text("text")).unwrap(),
+            verbatim(b"text")
+        );
+    }
+
+    #[test]
+    fn tells_empty_and_unavailable_clipboards_apart() {
+        let unavailable = Mock::unavailable();
+        assert_eq!(
+            paste(&[]).paste(&Mock::default()).unwrap(),
+            Pasted::Nothing(EXIT_EMPTY)
+        );
+        assert_eq!(
+            paste(&[]).paste(&unavailable).unwrap(),
+            Pasted::Nothing(EXIT_UNAVAILABLE)
+        );
+
+        for clipboard in [Mock::default(), unavailable] {
+            assert_eq!(
+                paste(&["--or-else", "none", "--no-newline"])
+                    .paste(&clipboard)
+                    .unwrap(),
+                verbatim(b"none")
+            );
+        }
+        assert_eq!(
+            paste(&["--allow-empty", "--no-newline"])
+                .paste(&Mock::default())
+                .unwrap(),
+            verbatim(b"")
+        );
+    }
+
+    #[test]
+    fn pastes_targets() {
+        let clipboard = Mock::with(Contents::Html {
+            html: "<b>a</b>".to_string(),
+            text: "a".to_string(),
+        });
+        assert_eq!(
+            paste(&["--targets"]).paste(&clipboard).unwrap(),
+            verbatim(b"text/html\ntext/plain\n")
+        );
+        assert_eq!(
+            paste(&["--target", "text/html"]).paste(&clipboard).unwrap(),
+            verbatim(b"<b>a</b>")
+        );
+        assert!(paste(&["--target", "image/png"]).paste(&clipboard).is_err());
+    }
+
+    #[test]
+    fn pastes_images() {
+        let image = Mock::with(Contents::Image {
+            data: b"png".to_vec(),
+            mime_type: "image/png".to_string(),
+        });
+        assert_eq!(
+            paste(&["--image", "-"]).paste(&image).unwrap(),
+            verbatim(b"png")
+        );
+        assert!(paste(&["--image", "-"]).paste(&text("text")).is_err());
+        assert_eq!(
+            paste(&["--prefer", "image"]).paste(&image).unwrap(),
+            verbatim(b"png")
+        );
+        assert_eq!(
+            paste(&["--prefer", "image", "--no-newline"])
+                .paste(&text("text"))
+                .unwrap(),
+            verbatim(b"text")
+        );
+    }
+
+    #[test]
+    fn converts_line_endings() {
+        assert_eq!(
+            paste(&["--crlf", "--no-newline"])
+                .paste(&text("a\nb"))
+                .unwrap(),
+            verbatim(b"a\r\nb")
+        );
+        assert_eq!(
+            paste(&["--lf", "--no-newline"])
+                .paste(&text("a\r\nb"))
+                .unwrap(),
+            verbatim(b"a\nb")
+        );
+    }
+
+    #[test]
+    fn pastes_binary_contents_copied_as_base64() {
+        let data: Vec<u8> = [0, 0, 0xff, b'\n', b'\r', 0x80]
+            .into_iter()
+            .chain((0..=255).cycle().take(1000))
+            .collect();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("binary");
+        std::fs::write(&path, &data).unwrap();
+
+        let clipboard = Mock::default();
+        CopyCli::parse_from(["copy", "--base64", path.to_str().unwrap()])
+            .copy
+            .copy(&clipboard)
+            .unwrap();
+        let Some(Contents::Text(encoded)) = clipboard.contents() else {
+            panic!("copied {:?}", clipboard.contents());
+        };
+        assert!(encoded.is_ascii());
+        assert_eq!(
+            paste(&["--decode-base64"]).paste(&clipboard).unwrap(),
+            verbatim(&data)
+        );
+
+        // encoded contents wrapped into lines, as by MIME or PEM
+        for (width, ending) in [(76, "\r\n"), (64, "\n")] {
+            let lines: Vec<&str> = encoded
+                .as_bytes()
+                .chunks(width)
+                .map(|line| std::str::from_utf8(line).unwrap())
+                .collect();
+            let wrapped = format!("{}{}", lines.join(ending), ending);
+            assert_eq!(
+                paste(&["--decode-base64"]).paste(&text(&wrapped)).unwrap(),
+                verbatim(&data),
+                "decoding lines of {width} characters"
+            );
+        }
 
-        println!("{}", contents);
-        Ok(())
+        assert!(
+            paste(&["--decode-base64"])
+                .paste(&text("not base64!"))
+                .is_err()
+        );
     }
 }
@@ -1,10 +1,14 @@
-use crate::{cli::Runnable, utils::file_keeper::validate_paths};
+use crate::{
+    cli::{Context, Runnable},
+    utils::file_keeper::validate_paths,
+};
 use clap::Args;
 use color_eyre::{eyre::bail, eyre::Result};
 use dialoguer::Confirm;
 use regex::Regex;
 use std::fs::copy;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 #[derive(Args, Debug)]
 pub struct Restore {
@@ -16,12 +20,13 @@ pub struct Restore {
 }
 
 impl Runnable for Restore {
-    fn run(&mut self) -> Result<()> {
+    fn run(&mut self, _ctx: &Context) -> Result<ExitCode> {
         if let Err(e) = validate_paths(&self.source, &mut self.target, false) {
             bail!("Restore validation failed: {}", e);
         }
 
-        self.restore()
+        self.restore()?;
+        Ok(ExitCode::SUCCESS)
     }
 }
 
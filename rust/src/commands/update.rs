@@ -1,40 +1,1136 @@
-use crate::cli::Runnable;
-use clap::Args;
-use color_eyre::eyre::{bail, Result};
-use reqwest::blocking::get;
-use version_compare::Version;
+use crate::cli::{Context, Runnable};
+use crate::utils::style::{Style, Styler};
+use chrono::DateTime;
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{Result, bail, eyre};
+use regex::Regex;
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::{Certificate, NoProxy, Proxy};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{self, rename};
+use std::io::{ErrorKind, Write, stdout};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::Duration;
+use std::{env, fmt, thread};
+use tempfile::{Builder, NamedTempFile, tempdir};
+use tracing::{debug, error, info, warn};
+
+/// Releases published on GitHub
+const RELEASES_URL: &str = "https://api.github.com/repos/Diomeh/dsu/releases";
+
+/// Version file of the default branch, checked instead when the GitHub API refuses requests
+const VERSION_URL: &str = "https://raw.githubusercontent.com/Diomeh/dsu/master/VERSION";
+
+/// Lines of release notes shown in the changelog, longer ones being cut with a link to the release
+const NOTES_LINES: usize = 30;
+
+/// Releases fetched per request of the changelog, the most the GitHub API gives
+const PER_PAGE: usize = 100;
+
+/// Exit code of updates that couldn't reach GitHub, such as when offline
+const EXIT_OFFLINE: u8 = 5;
+
+/// Retries of requests failing for reasons that may not last, such as a DNS lookup timing out
+const RETRIES: u32 = 2;
+
+/// Time before the first retry of a request, doubling with every other one
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// Least time downloads of releases are given, slower than requests to the API
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Versions as Semantic Versioning has them, such as `2.3.0`, `2.3.0-rc.1` or `2.3.0+build.5`
+const SEMVER: &str = r"^\d+\.\d+\.\d+(?:-[0-9A-Za-z-]+(?:\.[0-9A-Za-z-]+)*)?(?:\+[0-9A-Za-z-]+(?:\.[0-9A-Za-z-]+)*)?$";
+
+/// Characters of unexpected responses quoted in errors, such as the HTML page of a captive portal
+const EXCERPT_CHARS: usize = 80;
+
+/// Files of checksums that releases may publish instead of a `.sha256` file per asset, listing `<sha256>  <name>` lines
+const CHECKSUMS: [&str; 3] = ["SHA256SUMS", "sha256sums.txt", "checksums.txt"];
+
+/// Exit code of `--check` when a newer version is available
+const EXIT_UPDATE_AVAILABLE: u8 = 10;
+
+/// Format used to print the result of the version check
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    /// Human readable messages, or nothing but the exit code with `--check`
+    #[default]
+    Text,
+    /// A JSON object with the current and latest versions on a single line, errors being an object as well on stderr
+    Json,
+}
+
+/// Releases considered when looking for the latest version
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// Only stable releases
+    #[default]
+    Stable,
+    /// Prereleases as well, such as release candidates and nightly builds
+    Pre,
+}
 
 #[derive(Args, Debug)]
-pub struct Update {}
+pub struct Update {
+    /// Only tell whether a newer version is available, exiting with 10 if so, printing nothing but `--output`
+    #[arg(long, conflicts_with = "install")]
+    pub check: bool,
 
-impl Runnable for Update {
-    fn run(&mut self) -> Result<()> {
-        let url = "https://raw.githubusercontent.com/Diomeh/dsu/master/VERSION";
+    /// Format used to print the result of the version check, `json` leaving out the changelog
+    #[arg(long, value_enum, default_value_t = Output::Text, conflicts_with = "changelog")]
+    pub output: Output,
 
-        let response = get(url)?;
+    /// Show the release notes of the running version when it's the latest one, as for newer versions otherwise
+    #[arg(long, conflicts_with = "check")]
+    pub changelog: bool,
 
-        if !response.status().is_success() {
-            bail!("Failed to check for updates: {}", response.status());
+    /// Download the latest release and replace the running binary with it, keeping the previous one as `dsu.old`
+    #[arg(long)]
+    pub install: bool,
+
+    /// Proxy to send requests through instead of the one set with `HTTPS_PROXY`, such as `http://proxy:3128`
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// PEM file of certificates to trust on top of the system ones, such as the ones of a private CA
+    #[arg(long, value_name = "FILE")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Releases considered when looking for the latest version [default: stable]
+    #[arg(long, value_enum)]
+    pub channel: Option<Channel>,
+
+    /// Give up on requests not answered within the duration, such as `10s`, retrying twice those that may succeed later
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "5s")]
+    pub timeout: Duration,
+
+    /// Public key to verify the signature of the downloaded build with, either a minisign or a GPG one
+    #[arg(long, value_name = "FILE", requires = "install")]
+    pub verify_key: Option<PathBuf>,
+
+    /// Release to install instead of the latest one, such as `v2.3.0`, even if older than the running one
+    #[arg(long, value_name = "TAG", requires = "install")]
+    pub version: Option<String>,
+}
+
+/// Release as given by the GitHub API, only its tag being known when read from the version file
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    published_at: Option<String>,
+    /// Page of the release, with its notes
+    html_url: Option<String>,
+    /// Release notes, as Markdown
+    body: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    assets: Vec<Asset>,
+    /// Whether only the version file could be read, the GitHub API being rate limited, leaving the assets unknown
+    #[serde(skip)]
+    from_version_file: bool,
+}
+
+impl Release {
+    /// Version released, without the `v` its tag may start with
+    fn version(&self) -> &str {
+        let tag = self.tag_name.trim();
+        tag.strip_prefix('v').unwrap_or(tag)
+    }
+
+    /// Channel the release was published on
+    fn channel(&self) -> &'static str {
+        if self.prerelease {
+            "prerelease"
+        } else {
+            "stable"
         }
+    }
 
-        let remote_version = response.text()?;
-        let remote_version = remote_version.trim();
-        let remote_version = &remote_version[1..]; // Remove the 'v' prefix
-        let current_version = env!("CARGO_PKG_VERSION");
+    /// Day the release was published on, such as `2025-06-01`
+    fn released_on(&self) -> Option<String> {
+        let published = DateTime::parse_from_rfc3339(self.published_at.as_deref()?).ok()?;
+        Some(published.format("%Y-%m-%d").to_string())
+    }
+
+    /// Asset named `name`, if the release has one
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+/// Result of the version check, printed with `--output json`
+#[derive(Debug, Serialize)]
+struct Check<'a> {
+    current: &'a str,
+    latest: &'a str,
+    update_available: bool,
+    published_at: Option<&'a str>,
+    notes_url: Option<&'a str>,
+    /// Build installed with `--install`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installed: Option<Installed>,
+}
+
+/// Build installed in place of the running one
+#[derive(Debug, Serialize)]
+struct Installed {
+    version: String,
+    path: PathBuf,
+    /// Where the previous binary is kept
+    previous: PathBuf,
+    verification: Verification,
+}
+
+/// How a downloaded build was verified before being installed
+#[derive(Debug, Serialize)]
+struct Verification {
+    algorithm: &'static str,
+    digest: String,
+    /// Tool the signature was verified with, when given a key
+    signature: Option<&'static str>,
+}
+
+/// File attached to a release, such as a build for a platform
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Runnable for Update {
+    fn run(&mut self, ctx: &Context) -> Result<ExitCode> {
+        // scripts may well carry on when offline, as an update can wait
+        match self.update(ctx) {
+            Err(err) if self.output == Output::Json => {
+                eprintln!("{}", serde_json::json!({ "error": err.to_string() }));
+                Ok(if err.is::<Offline>() {
+                    ExitCode::from(EXIT_OFFLINE)
+                } else {
+                    ExitCode::FAILURE
+                })
+            }
+            Err(err) if err.is::<Offline>() => {
+                error!("{}", err);
+                Ok(ExitCode::from(EXIT_OFFLINE))
+            }
+            result => result,
+        }
+    }
+}
 
-        let remote_version = Version::from(remote_version).unwrap();
-        let current_version = Version::from(current_version).unwrap();
+impl Update {
+    /// Checks for a newer version, showing its changelog, and installs it if asked to
+    fn update(&self, ctx: &Context) -> Result<ExitCode> {
+        let github = Github::new(self.proxy.as_deref(), self.ca_cert.as_deref(), self.timeout)?;
+        let channel = self
+            .channel
+            .or(ctx.config.update.channel)
+            .unwrap_or_default();
+        if self.check || self.output == Output::Json {
+            return self.check(&github, channel, ctx.dry_run);
+        }
 
         println!("Checking for updates...");
+        let release = match &self.version {
+            Some(tag) => tagged_release(&github, tag)?,
+            None => latest_release(&github, channel)?,
+        };
+
+        let remote_version = release.version();
+        let current_version = env!("CARGO_PKG_VERSION");
+
         println!("Current version: {}", current_version);
 
-        if remote_version > current_version {
-            println!("A new version is available: {}", remote_version);
-            println!("Refer to the repo README on how to update: https://github.com/Diomeh/dsu/blob/master/README.md");
+        if self.version.is_some() {
+            self.install(&github, &release, ctx.dry_run)?;
+        } else if compare_versions(remote_version, current_version) == Ordering::Greater {
+            match release.released_on() {
+                Some(day) => println!(
+                    "A new version is available: {} ({}), released on {}",
+                    remote_version,
+                    release.channel(),
+                    day
+                ),
+                None => println!(
+                    "A new version is available: {} ({})",
+                    remote_version,
+                    release.channel()
+                ),
+            }
+            if let Some(url) = &release.html_url {
+                println!("Release notes: {}", url);
+            }
+            // the update is offered all the same when the changelog can't be fetched
+            match releases(&github, channel) {
+                Ok(releases) => {
+                    let newer = releases.iter().filter(|release| {
+                        compare_versions(release.version(), current_version) == Ordering::Greater
+                    });
+                    print_changelog(newer, Styler::new(ctx.color, &stdout()));
+                }
+                Err(err) => warn!("Failed to get the changelog: {}", err),
+            }
+            if self.install {
+                self.install(&github, &release, ctx.dry_run)?;
+            } else {
+                println!("Run `dsu update --install` to install it");
+            }
         } else {
             println!("You are running the latest version: {}", current_version);
+            if self.changelog {
+                let releases = releases(&github, channel)?;
+                let current = releases.iter().filter(|release| {
+                    compare_versions(release.version(), current_version) == Ordering::Equal
+                });
+                print_changelog(current, Styler::new(ctx.color, &stdout()));
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Tells whether a newer version is available, with the exit code alone for `--check`,
+    /// so that scripts needn't parse anything, and as JSON with `--output json`, along with the build installed.
+    fn check(&self, github: &Github, channel: Channel, dry_run: bool) -> Result<ExitCode> {
+        let release = match &self.version {
+            Some(tag) => tagged_release(github, tag)?,
+            None => latest_release(github, channel)?,
+        };
+        let latest = release.version();
+        let current = env!("CARGO_PKG_VERSION");
+        let update_available = compare_versions(latest, current) == Ordering::Greater;
+
+        let installed = match self.install && (update_available || self.version.is_some()) {
+            true => self.install(github, &release, dry_run)?,
+            false => None,
+        };
+
+        if self.output == Output::Json {
+            let check = Check {
+                current,
+                latest,
+                update_available,
+                published_at: release.published_at.as_deref(),
+                notes_url: release.html_url.as_deref(),
+                installed,
+            };
+            println!("{}", serde_json::to_string(&check)?);
+        }
+
+        Ok(if update_available && self.check {
+            ExitCode::from(EXIT_UPDATE_AVAILABLE)
+        } else {
+            ExitCode::SUCCESS
+        })
+    }
+
+    /// Replaces the running binary with the one of `release` built for the current platform, keeping the previous
+    /// binary next to it with an `.old` extension, giving the build installed unless `dry_run`.
+    ///
+    /// Nothing is replaced unless the build matches the SHA-256 published along with it, and the signature
+    /// published with it when given a key. The new binary is written next to the running one and renamed over it,
+    /// so that it is never left half written.
+    fn install(
+        &self,
+        github: &Github,
+        release: &Release,
+        dry_run: bool,
+    ) -> Result<Option<Installed>> {
+        let text = self.output == Output::Text;
+        let name = asset_name();
+        if release.from_version_file {
+            bail!(
+                "Release assets are unavailable, the GitHub API rate limit was exceeded, which GITHUB_TOKEN avoids"
+            );
+        }
+        let Some(asset) = release.asset(&name) else {
+            bail!(
+                "Release {} has no build for this platform, {}",
+                release.tag_name,
+                name
+            );
+        };
+
+        let exe = env::current_exe()?.canonicalize()?;
+        let dir = exe
+            .parent()
+            .ok_or_else(|| eyre!("Unable to determine the directory of {:?}", exe))?;
+        let mut staged = match Builder::new().prefix(".dsu-update").tempfile_in(dir) {
+            Ok(staged) => staged,
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => bail!(
+                "Unable to replace {:?}, its directory isn't writable: run the update with sudo, \
+                 or install dsu to a directory of your own, such as ~/.local/bin",
+                exe
+            ),
+            Err(err) => bail!("Failed to write to {:?}: {}", dir, err),
+        };
+
+        if dry_run {
+            if text {
+                println!("Would install {} to {:?}", release.version(), exe);
+            }
+            return Ok(None);
+        }
+
+        let expected = expected_digest(github, release, &name)?;
+        info!("Downloading {}", asset.browser_download_url);
+        let binary = download(github, asset)?;
+        let digest: String = Sha256::digest(&binary)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        if digest != expected {
+            bail!(
+                "Downloaded {} doesn't match its checksum, not installing it: expected {}, got {}",
+                name,
+                expected,
+                digest
+            );
+        }
+        info!("Verified the SHA-256 of {}: {}", name, digest);
+
+        staged.write_all(&binary)?;
+        staged.as_file().sync_all()?;
+        let signature = match &self.verify_key {
+            Some(key) => {
+                let tool = verify_signature(github, release, &name, staged.path(), key)?;
+                info!("Verified the {} signature of {}", tool, name);
+                Some(tool)
+            }
+            None => None,
+        };
+        fs::set_permissions(staged.path(), fs::metadata(&exe)?.permissions())?;
+        let previous = replace(&exe, staged)?;
+
+        if text {
+            println!(
+                "Updated to {}, the previous version is kept as {:?}",
+                release.version(),
+                previous
+            );
+        }
+        Ok(Some(Installed {
+            version: release.version().to_string(),
+            path: exe,
+            previous,
+            verification: Verification {
+                algorithm: "sha256",
+                digest,
+                signature,
+            },
+        }))
+    }
+}
+
+/// Latest release of dsu on `channel`, as given by the GitHub API, authenticated with `GITHUB_TOKEN` when set,
+/// or only its version as read from the version file once the API rate limit of stable releases is exceeded.
+///
+/// Prereleases are looked for among the latest releases made, which the latest version is found among.
+fn latest_release(github: &Github, channel: Channel) -> Result<Release> {
+    if channel == Channel::Pre {
+        let releases = release_page(github, 1)?;
+        return releases
+            .into_iter()
+            .filter(|release| !release.draft)
+            .max_by(|a, b| compare_versions(a.version(), b.version()))
+            .ok_or_else(|| eyre!("No release has been published"));
+    }
+
+    let response = github.api(&format!("{}/latest", RELEASES_URL))?;
+
+    if is_rate_limited(&response) {
+        warn!(
+            "GitHub API rate limit exceeded, checking the version file instead, which GITHUB_TOKEN avoids"
+        );
+        let response = github.get(VERSION_URL)?;
+        if !response.status().is_success() {
+            bail!("Failed to check for updates: {}", response.status());
+        }
+        let version = parse_version(&response.text()?)?.to_string();
+        return Ok(Release {
+            tag_name: version,
+            published_at: None,
+            html_url: None,
+            body: None,
+            draft: false,
+            prerelease: false,
+            assets: Vec::new(),
+            from_version_file: true,
+        });
+    }
+    if !response.status().is_success() {
+        bail!("Failed to check for updates: {}", response.status());
+    }
+
+    let release: Release = parse_json(&response.text()?)?;
+    parse_version(&release.tag_name)?;
+    debug!(
+        "Latest release {} has assets: {}",
+        release.tag_name,
+        release
+            .assets
+            .iter()
+            .map(|asset| asset.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(release)
+}
+
+/// Every published release of dsu on `channel`, from the newest one, without drafts
+fn releases(github: &Github, channel: Channel) -> Result<Vec<Release>> {
+    let mut releases = Vec::new();
+    for page in 1.. {
+        let page = release_page(github, page)?;
+        let last = page.len() < PER_PAGE;
+        releases.extend(
+            page.into_iter().filter(|release| {
+                !release.draft && (channel == Channel::Pre || !release.prerelease)
+            }),
+        );
+        if last {
+            break;
+        }
+    }
+    Ok(releases)
+}
+
+/// Releases of dsu listed on the `page`th page, from the newest one, drafts included
+fn release_page(github: &Github, page: usize) -> Result<Vec<Release>> {
+    let response = github.api(&format!(
+        "{}?per_page={}&page={}",
+        RELEASES_URL, PER_PAGE, page
+    ))?;
+    if is_rate_limited(&response) {
+        bail!("GitHub API rate limit exceeded, which GITHUB_TOKEN avoids");
+    }
+    if !response.status().is_success() {
+        bail!("Failed to list releases: {}", response.status());
+    }
+    let releases: Vec<Release> = parse_json(&response.text()?)?;
+    // tags that aren't versions can't be ordered, and are never the latest version
+    Ok(releases
+        .into_iter()
+        .filter(|release| match parse_version(&release.tag_name) {
+            Ok(_) => true,
+            Err(err) => {
+                debug!("Skipping release: {}", err);
+                false
+            }
+        })
+        .collect())
+}
+
+/// Version given by `text`, as a tag or the version file has it, without whitespace around it
+/// or the `v` it may start with, failing unless it's a version as Semantic Versioning has it.
+fn parse_version(text: &str) -> Result<&str> {
+    let text = text.trim();
+    let version = text.strip_prefix('v').unwrap_or(text);
+    if version.is_empty() {
+        bail!("Expected a version such as 1.2.3, got nothing");
+    }
+    if !Regex::new(SEMVER)?.is_match(version) {
+        bail!("Expected a version such as 1.2.3, got {:?}", excerpt(text));
+    }
+    Ok(version)
+}
+
+/// Response of the GitHub API parsed from `text`, failing with what was received instead when it isn't the JSON
+/// expected, as when a captive portal answers with an HTML page.
+fn parse_json<T: DeserializeOwned>(text: &str) -> Result<T> {
+    match serde_json::from_str(text) {
+        Ok(value) => Ok(value),
+        Err(_) if text.trim().is_empty() => {
+            bail!("Expected JSON from GitHub, got an empty response")
+        }
+        Err(err) => bail!(
+            "Expected JSON from GitHub, got {:?}: {}",
+            excerpt(text),
+            err
+        ),
+    }
+}
+
+/// Start of `text` on a single line, to be quoted in errors
+fn excerpt(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line,
+    }
+}
+
+/// Order of versions as Semantic Versioning has it, `version_compare` taking prerelease identifiers
+/// for more parts: `1.3.0-rc.1` comes before `1.3.0`, and `1.3.0-rc.2` before `1.3.0-rc.10`.
+///
+/// Build metadata, following `+`, is ignored.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| {
+        let version = version.split('+').next().unwrap_or_default();
+        match version.split_once('-') {
+            Some((core, pre)) => (core.to_string(), Some(pre.to_string())),
+            None => (version.to_string(), None),
+        }
+    };
+    let ((a_core, a_pre), (b_core, b_pre)) = (parts(a), parts(b));
+
+    let core = version_compare::compare(&a_core, &b_core)
+        .ok()
+        .and_then(|cmp| cmp.ord())
+        .unwrap_or(Ordering::Equal);
+    if core != Ordering::Equal {
+        return core;
+    }
+
+    match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            let (mut a, mut b) = (a.split('.'), b.split('.'));
+            loop {
+                let order = match (a.next(), b.next()) {
+                    (None, None) => return Ordering::Equal,
+                    (None, Some(_)) => return Ordering::Less,
+                    (Some(_), None) => return Ordering::Greater,
+                    // numeric identifiers come before alphanumeric ones
+                    (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                        (Ok(a), Ok(b)) => a.cmp(&b),
+                        (Ok(_), Err(_)) => Ordering::Less,
+                        (Err(_), Ok(_)) => Ordering::Greater,
+                        (Err(_), Err(_)) => a.cmp(b),
+                    },
+                };
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+        }
+    }
+}
+
+/// Prints the notes of `releases` from the oldest one, each under its tag and the day it was released on
+fn print_changelog<'a>(releases: impl Iterator<Item = &'a Release>, styler: Styler) {
+    let mut releases: Vec<_> = releases.collect();
+    releases.sort_by(|a, b| compare_versions(a.version(), b.version()));
+
+    for release in releases {
+        let title = match release.released_on() {
+            Some(day) => format!("{} ({})", release.tag_name, day),
+            None => release.tag_name.clone(),
+        };
+        println!();
+        println!("{}", styler.paint(title, Style::BoldBlue));
+
+        let notes = render_notes(release.body.as_deref().unwrap_or_default(), styler);
+        for line in notes.iter().take(NOTES_LINES) {
+            println!("{}", line);
+        }
+        if notes.len() > NOTES_LINES
+            && let Some(url) = &release.html_url
+        {
+            println!("{}", styler.paint(format!("… see {}", url), Style::Dim));
+        }
+    }
+}
+
+/// Lines of release notes written in Markdown, with the headers styled and the bullets of lists drawn as such,
+/// leaving the rest as written.
+fn render_notes(notes: &str, styler: Styler) -> Vec<String> {
+    let mut lines: Vec<String> = notes
+        .lines()
+        .map(|line| {
+            let line = line.trim_end();
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let text = line.trim_start();
+            if let Some(header) = text.strip_prefix('#') {
+                styler.paint(header.trim_start_matches('#').trim(), Style::Yellow)
+            } else if let Some(item) = ["- ", "* ", "+ "]
+                .iter()
+                .find_map(|bullet| text.strip_prefix(bullet))
+            {
+                format!("{}• {}", indent, item.replace("**", ""))
+            } else {
+                line.replace("**", "")
+            }
+        })
+        .collect();
+
+    // blank lines around the notes only take room
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    let blank = lines.iter().take_while(|line| line.is_empty()).count();
+    lines.drain(..blank);
+    lines
+}
+
+/// Release of dsu tagged `tag`, with or without the `v` it starts with
+fn tagged_release(github: &Github, tag: &str) -> Result<Release> {
+    let tag = tag.trim();
+    let other = match tag.strip_prefix('v') {
+        Some(version) => version.to_string(),
+        None => format!("v{}", tag),
+    };
+
+    for tag in [tag, &other] {
+        let response = github.api(&format!("{}/tags/{}", RELEASES_URL, tag))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => continue,
+            status if status.is_success() => {
+                let release: Release = parse_json(&response.text()?)?;
+                parse_version(&release.tag_name)?;
+                return Ok(release);
+            }
+            _ if is_rate_limited(&response) => {
+                bail!("GitHub API rate limit exceeded, which GITHUB_TOKEN avoids")
+            }
+            status => bail!("Failed to look up release {}: {}", tag, status),
+        }
+    }
+
+    bail!("No release is tagged {}", tag);
+}
+
+/// Error of requests that couldn't reach GitHub after being retried, as when offline
+#[derive(Debug)]
+struct Offline;
+
+impl fmt::Display for Offline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Could not reach github.com, are you offline?")
+    }
+}
+
+impl Error for Offline {}
+
+/// Client of the GitHub API and of the downloads of releases, through the proxy in use if any
+struct Github {
+    client: Client,
+    /// Proxy requests are sent through, either given or set with `HTTPS_PROXY` or `ALL_PROXY`
+    proxy: Option<String>,
+    timeout: Duration,
+}
+
+impl Github {
+    /// Client sending requests through `proxy`, or the one of the environment, trusting the certificates
+    /// of `ca_cert`, or of `SSL_CERT_FILE`, on top of the system ones, as a private CA is in corporate networks.
+    ///
+    /// Requests are given up on when not answered within `timeout`, to connect and to be responded to.
+    fn new(proxy: Option<&str>, ca_cert: Option<&Path>, timeout: Duration) -> Result<Self> {
+        let mut builder = Client::builder().connect_timeout(timeout).timeout(timeout);
+
+        let proxy = match proxy {
+            Some(url) => {
+                let proxy = match Proxy::all(url) {
+                    Ok(proxy) => proxy,
+                    Err(err) => bail!("Invalid proxy {}: {}", url, err),
+                };
+                builder = builder
+                    .no_proxy()
+                    .proxy(proxy.no_proxy(NoProxy::from_env()));
+                Some(url.to_string())
+            }
+            None => ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+                .into_iter()
+                .find_map(|name| env::var(name).ok().filter(|url| !url.is_empty())),
+        };
+
+        let ca_cert = ca_cert
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os("SSL_CERT_FILE").map(PathBuf::from));
+        if let Some(path) = ca_cert {
+            let pem = match fs::read(&path) {
+                Ok(pem) => pem,
+                Err(err) => bail!("Failed to read CA certificate {:?}: {}", path, err),
+            };
+            let certificates = match Certificate::from_pem_bundle(&pem) {
+                Ok(certificates) if !certificates.is_empty() => certificates,
+                Ok(_) => bail!("No certificate found in {:?}", path),
+                Err(err) => bail!("Invalid CA certificate {:?}: {}", path, err),
+            };
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            proxy,
+            timeout,
+        })
+    }
+
+    /// Response of the GitHub API to a request of `url`
+    fn api(&self, url: &str) -> Result<Response> {
+        self.send(
+            self.client
+                .get(url)
+                .header("Accept", "application/vnd.github+json"),
+        )
+    }
+
+    /// Response to a request of `url`, such as the version file
+    fn get(&self, url: &str) -> Result<Response> {
+        self.send(self.client.get(url))
+    }
+
+    /// Response to the download of an asset at `url`, given more time than other requests
+    fn download(&self, url: &str) -> Result<Response> {
+        self.send(
+            self.client
+                .get(url)
+                .timeout(self.timeout.max(DOWNLOAD_TIMEOUT)),
+        )
+    }
+
+    /// Sends `request` with a user agent, which GitHub refuses requests without, authenticated with `GITHUB_TOKEN`
+    /// when set, telling whether the proxy or the verification of certificates is to blame when failing.
+    ///
+    /// Requests failing to connect, timing out or failing on the side of the server are retried, with a backoff.
+    fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let mut request = request.header("User-Agent", concat!("dsu/", env!("CARGO_PKG_VERSION")));
+        if let Ok(token) = env::var("GITHUB_TOKEN")
+            && !token.is_empty()
+        {
+            request = request.bearer_auth(token);
+        }
+
+        let mut attempt = 0;
+        let err = loop {
+            // requests have no body, so that they can always be cloned
+            let result = request.try_clone().unwrap().send();
+            let retried = attempt < RETRIES;
+            match result {
+                Ok(response) if response.status().is_server_error() && retried => {
+                    debug!("Retrying request, failed with {}", response.status());
+                }
+                Ok(response) => return Ok(response),
+                Err(err) if (err.is_connect() || err.is_timeout()) && !is_tls(&err) && retried => {
+                    debug!("Retrying request, failed: {}", err);
+                }
+                Err(err) => break err,
+            }
+            thread::sleep(BACKOFF * 2u32.pow(attempt));
+            attempt += 1;
+        };
+
+        let mut causes = vec![err.to_string()];
+        let mut source = err.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        let causes = causes.join(": ");
+
+        if is_tls(&err) {
+            bail!(
+                "TLS verification failed, a private CA can be trusted with --ca-cert: {}",
+                causes
+            );
+        }
+        match &self.proxy {
+            Some(proxy) if err.is_connect() => {
+                bail!("Failed to connect through the proxy {}: {}", proxy, causes)
+            }
+            _ if err.is_connect() || err.is_timeout() => {
+                debug!("Failed to reach GitHub: {}", causes);
+                Err(Offline.into())
+            }
+            _ => bail!("{}", causes),
+        }
+    }
+}
+
+/// Whether `err` comes from establishing a TLS connection, such as verifying the certificate of the server
+fn is_tls(err: &reqwest::Error) -> bool {
+    let mut source = err.source();
+    while let Some(cause) = source {
+        let cause_text = cause.to_string().to_lowercase();
+        if ["certificate", "tls", "ssl"]
+            .iter()
+            .any(|word| cause_text.contains(word))
+        {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// Whether GitHub refused the request for exceeding the API rate limit, rather than for another reason
+fn is_rate_limited(response: &Response) -> bool {
+    matches!(
+        response.status(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) && response
+        .headers()
+        .get("x-ratelimit-remaining")
+        .is_some_and(|remaining| remaining == "0")
+}
+
+/// Name of the release asset built for the current platform, such as `dsu-x86_64-linux`
+fn asset_name() -> String {
+    format!(
+        "dsu-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+/// SHA-256 published for the asset `name` of `release`, either in `<name>.sha256` or in a file of checksums
+fn expected_digest(github: &Github, release: &Release, name: &str) -> Result<String> {
+    let (asset, listed) = match release.asset(&format!("{}.sha256", name)) {
+        Some(asset) => (asset, false),
+        None => match CHECKSUMS.iter().find_map(|file| release.asset(file)) {
+            Some(asset) => (asset, true),
+            None => bail!(
+                "Release {} publishes no checksum for {}, not installing it",
+                release.tag_name,
+                name
+            ),
+        },
+    };
+
+    let checksums = String::from_utf8_lossy(&download(github, asset)?).into_owned();
+    let digest = checksums.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let digest = fields.next()?;
+        // binary files are listed with a `*` before their name by `sha256sum --binary`
+        let listed_name = fields.next().map(|field| field.trim_start_matches('*'));
+        (!listed || listed_name == Some(name)).then(|| digest.to_ascii_lowercase())
+    });
+    match digest {
+        Some(digest) if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(digest)
         }
+        Some(_) => bail!(
+            "Invalid checksum of {} in {}, not installing it",
+            name,
+            asset.name
+        ),
+        None => bail!(
+            "{} lists no checksum for {}, not installing it",
+            asset.name,
+            name
+        ),
+    }
+}
+
+/// Verifies the signature of the build `name` written to `binary` with the public `key`, giving the tool used,
+/// `minisign` for minisign keys, with the signature published as `<name>.minisig`, and `gpg` for others,
+/// with the signature published as `<name>.asc` or `<name>.sig`.
+///
+/// GPG keys are imported into a keyring of their own, so that the keys of the user are never trusted instead.
+fn verify_signature(
+    github: &Github,
+    release: &Release,
+    name: &str,
+    binary: &Path,
+    key: &Path,
+) -> Result<&'static str> {
+    let contents = match fs::read(key) {
+        Ok(contents) => contents,
+        Err(err) => bail!("Failed to read key {:?}: {}", key, err),
+    };
+    let minisign = String::from_utf8_lossy(&contents).starts_with("untrusted comment:");
+
+    let extensions: &[&str] = if minisign {
+        &["minisig"]
+    } else {
+        &["asc", "sig"]
+    };
+    let Some(asset) = extensions
+        .iter()
+        .find_map(|extension| release.asset(&format!("{}.{}", name, extension)))
+    else {
+        bail!(
+            "Release {} publishes no signature for {}, not installing it",
+            release.tag_name,
+            name
+        );
+    };
+    let mut signature = NamedTempFile::new()?;
+    signature.write_all(&download(github, asset)?)?;
+
+    let (tool, output) = if minisign {
+        let output = Command::new("minisign")
+            .arg("-Vq")
+            .arg("-p")
+            .arg(key)
+            .arg("-m")
+            .arg(binary)
+            .arg("-x")
+            .arg(signature.path())
+            .output();
+        ("minisign", output)
+    } else {
+        let home = tempdir()?;
+        let gpg = |args: &[&OsStr]| {
+            Command::new("gpg")
+                .arg("--homedir")
+                .arg(home.path())
+                .args(["--batch", "--quiet"])
+                .args(args)
+                .output()
+        };
+        let output = gpg(&["--import".as_ref(), key.as_os_str()]).and_then(|output| {
+            match output.status.success() {
+                true => gpg(&[
+                    "--verify".as_ref(),
+                    signature.path().as_os_str(),
+                    binary.as_os_str(),
+                ]),
+                false => Ok(output),
+            }
+        });
+        ("gpg", output)
+    };
+
+    match output {
+        Ok(output) if output.status.success() => Ok(tool),
+        Ok(output) => bail!(
+            "Signature of {} doesn't match key {:?}, not installing it: {}",
+            name,
+            key,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(err) => bail!(
+            "Failed to run {}, needed to verify the signature: {}",
+            tool,
+            err
+        ),
+    }
+}
+
+/// Contents of `asset`
+fn download(github: &Github, asset: &Asset) -> Result<Vec<u8>> {
+    let response = github.download(&asset.browser_download_url)?;
+    if !response.status().is_success() {
+        bail!("Failed to download {}: {}", asset.name, response.status());
+    }
+    Ok(response.bytes()?.to_vec())
+}
+
+/// Renames `staged` over the binary at `exe`, giving where the previous one is kept.
+///
+/// Windows doesn't allow replacing a running binary, but does allow renaming it, so it is moved aside first there,
+/// whereas it's copied elsewhere, so that `exe` is never missing.
+fn replace(exe: &Path, staged: NamedTempFile) -> Result<PathBuf> {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".old");
+    let old = exe.with_file_name(name);
+
+    if cfg!(windows) {
+        let _ = fs::remove_file(&old);
+        rename(exe, &old)?;
+        if let Err(err) = staged.persist(exe) {
+            let _ = rename(&old, exe);
+            bail!("Failed to replace {:?}: {}", exe, err.error);
+        }
+    } else {
+        fs::copy(exe, &old)?;
+        if let Err(err) = staged.persist(exe) {
+            bail!("Failed to replace {:?}: {}", exe, err.error);
+        }
+    }
+    Ok(old)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse_version("1.2.3").unwrap(), "1.2.3");
+        assert_eq!(parse_version("v1.2.3\n").unwrap(), "1.2.3");
+        assert_eq!(
+            parse_version(" 1.2.3-rc.1+build.5 ").unwrap(),
+            "1.2.3-rc.1+build.5"
+        );
+    }
+
+    #[test]
+    fn refuses_empty_versions() {
+        for text in ["", "\n", "v"] {
+            let err = parse_version(text).unwrap_err().to_string();
+            assert!(err.ends_with("got nothing"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_versions() {
+        for text in ["1.2", "1.2.3.4", "v1.2.x", "1.2.3-", "latest", "<html>"] {
+            let err = parse_version(text).unwrap_err().to_string();
+            assert!(err.contains(&format!("{:?}", text)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn refuses_responses_that_arent_json() {
+        let err = parse_json::<Release>("").unwrap_err().to_string();
+        assert!(err.ends_with("got an empty response"), "{}", err);
+
+        let portal = "<!DOCTYPE html>\n<html>\n<head><title>Sign in to the network</title></head>\n\
+                      <body><form action=\"/login\">Accept the terms of use to continue</form></body>\n</html>\n";
+        let err = parse_json::<Release>(portal).unwrap_err().to_string();
+        assert!(
+            err.contains("<!DOCTYPE html> <html> <head><title>Sign in"),
+            "{}",
+            err
+        );
+        assert!(err.contains("..."), "{}", err);
+        assert!(!err.contains("</html>"), "{}", err);
+    }
+
+    #[test]
+    fn parses_releases() {
+        let release: Release = parse_json(r#"{"tag_name": "v1.2.3", "prerelease": true}"#).unwrap();
+        assert_eq!(release.version(), "1.2.3");
+        assert_eq!(release.channel(), "prerelease");
+    }
+
+    #[test]
+    fn orders_versions() {
+        assert_eq!(compare_versions("1.2.3", "1.2.3"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2.10", "1.2.9"), Ordering::Greater);
+        assert_eq!(compare_versions("2.0.0", "1.99.99"), Ordering::Greater);
+        assert_eq!(
+            compare_versions("1.2.3+build.1", "1.2.3+build.2"),
+            Ordering::Equal
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn orders_prereleases_before_their_version() {
+        assert_eq!(compare_versions("1.2.3-rc.1", "1.2.3"), Ordering::Less);
+        assert_eq!(
+            compare_versions("1.2.3-rc.2", "1.2.3-rc.10"),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_versions("1.2.3-alpha", "1.2.3-alpha.1"),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_versions("1.2.3-alpha.1", "1.2.3-beta"),
+            Ordering::Less
+        );
+        assert_eq!(compare_versions("1.2.3-1", "1.2.3-alpha"), Ordering::Less);
+        assert_eq!(compare_versions("1.2.4-rc.1", "1.2.3"), Ordering::Greater);
     }
 }
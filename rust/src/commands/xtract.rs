@@ -5,10 +5,11 @@ use std::{
     fs::{create_dir_all, read_dir, set_permissions, File, Permissions},
     io::{copy, BufReader, BufWriter},
     path::PathBuf,
+    process::ExitCode,
 };
 use tempfile::tempdir;
 
-use crate::cli::Runnable;
+use crate::cli::{Context, Runnable};
 
 #[derive(Args, Debug)]
 pub struct Xtract {
@@ -25,13 +26,14 @@ pub struct Xtract {
 }
 
 impl Runnable for Xtract {
-    fn run(&mut self) -> Result<()> {
+    fn run(&mut self, _ctx: &Context) -> Result<ExitCode> {
         // implies exists() == true
         if !self.archive.is_file() {
             bail!("Archive does not exist: {:?}", self.archive);
         }
 
-        self.process()
+        self.process()?;
+        Ok(ExitCode::SUCCESS)
     }
 }
 
@@ -135,10 +137,10 @@ impl Xtract {
                     outpath.display(),
                     file.size()
                 );
-                if let Some(p) = outpath.parent() {
-                    if !p.exists() {
-                        create_dir_all(p)?;
-                    }
+                if let Some(p) = outpath.parent()
+                    && !p.exists()
+                {
+                    create_dir_all(p)?;
                 }
                 let mut outfile = File::create(&outpath)?;
                 copy(&mut file, &mut outfile)?;
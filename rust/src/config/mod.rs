@@ -0,0 +1,120 @@
+use color_eyre::eyre::{Result, bail};
+use serde::{
+    Deserialize, Deserializer,
+    de::{MapAccess, Visitor},
+};
+use std::{env, fmt, fs::read_to_string, path::PathBuf};
+
+use crate::commands::update::Channel;
+
+/// Default values for command options, read from a TOML file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Defaults for the cln command
+    pub cln: ClnConfig,
+
+    /// Defaults for the hog command
+    pub hog: HogConfig,
+
+    /// Defaults for the update command
+    pub update: UpdateConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClnConfig {
+    /// Characters allowed in clean filenames on top of the default ones
+    pub allow: Option<String>,
+
+    /// Substitutions applied to filenames before cleaning them
+    pub map: Mappings,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HogConfig {
+    /// Reuse the sizes of unchanged directories from previous scans, same as `--cache`
+    pub cache: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdateConfig {
+    /// Releases considered when looking for the latest version, same as `--channel`
+    pub channel: Option<Channel>,
+}
+
+/// Table of `from = "to"` substitutions, kept in the order they were written in
+/// so that duplicate keys can be reported instead of silently overwriting each other.
+#[derive(Debug, Clone, Default)]
+pub struct Mappings(pub Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for Mappings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MappingsVisitor;
+
+        impl<'de> Visitor<'de> for MappingsVisitor {
+            type Value = Mappings;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a table of strings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Mappings(entries))
+            }
+        }
+
+        deserializer.deserialize_map(MappingsVisitor)
+    }
+}
+
+impl Config {
+    /// Loads the configuration file.
+    ///
+    /// An explicitly provided file, either through `path` or the `DSU_CONFIG` environment variable,
+    /// must exist, whereas a missing default file results in the default configuration.
+    pub fn load(path: Option<&PathBuf>) -> Result<Self> {
+        let explicit = path
+            .cloned()
+            .or_else(|| env::var_os("DSU_CONFIG").map(PathBuf::from));
+
+        let path = match explicit {
+            Some(path) => {
+                if !path.is_file() {
+                    bail!("Config file does not exist: {:?}", path);
+                }
+                path
+            }
+            None => match Self::default_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => bail!("Failed to read config file {:?}: {}", path, err),
+        };
+
+        match toml::from_str(&contents) {
+            Ok(config) => Ok(config),
+            Err(err) => bail!("Failed to parse config file {:?}: {}", path, err),
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/dsu/config.toml`, falling back to `~/.config/dsu/config.toml`
+    fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(config_dir.join("dsu").join("config.toml"))
+    }
+}
@@ -1,11 +1,13 @@
 mod cli;
 mod commands;
+mod config;
 mod utils;
 
 use clap::Parser;
 use color_eyre::Result;
+use std::process::ExitCode;
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     color_eyre::install()?;
 
     let mut cli = cli::Cli::parse();
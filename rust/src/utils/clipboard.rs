@@ -0,0 +1,1420 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::ValueEnum;
+use color_eyre::eyre::{Result, bail, eyre};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, IsTerminal, Read, Write, stderr, stdin, stdout},
+    os::unix::process::CommandExt,
+    process::{self, Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Length of the encoded text over which OSC 52 sequences are ignored by some terminals, such as hterm
+const OSC52_LIMIT: usize = 74_994;
+
+/// Length of the pieces OSC 52 sequences are split into within GNU screen, which truncates longer ones
+const SCREEN_CHUNK: usize = 768;
+
+/// MIME type of the images pasted, which clipboard owners convert other image formats to
+const PNG: &str = "image/png";
+
+/// MIME type of text, as which copied text is always available
+const TEXT: &str = "text/plain";
+
+/// Line written by the background process serving copied contents once they are on the clipboard
+const READY: &str = "ready";
+
+/// Clipboard copied to and pasted from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Wayland if `WAYLAND_DISPLAY` is set, then X11, then OSC 52 only to copy
+    #[default]
+    Auto,
+    /// Wayland compositors supporting the data control protocol
+    Wayland,
+    /// X11 server, or the native clipboard outside of Linux and BSDs
+    X11,
+    /// Terminal escape sequence, only able to copy
+    Osc52,
+    /// File under `$XDG_RUNTIME_DIR`, only shared with the processes of the user on the same machine
+    File,
+}
+
+/// MIME type of rich text
+const HTML: &str = "text/html";
+
+/// Contents put on the clipboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Contents {
+    Text(String),
+    /// Image data of the given MIME type, such as `image/png`
+    Image {
+        data: Vec<u8>,
+        mime_type: String,
+    },
+    /// Rich text, along with the plain text pasted by applications that don't take HTML
+    Html {
+        html: String,
+        text: String,
+    },
+}
+
+impl Contents {
+    /// Representations of the contents, by MIME type, that the clipboard offers
+    fn flavors(&self) -> Vec<(&str, &[u8])> {
+        match self {
+            Contents::Text(text) => vec![(TEXT, text.as_bytes())],
+            Contents::Image { data, mime_type } => vec![(mime_type, data)],
+            Contents::Html { html, text } => vec![(HTML, html.as_bytes()), (TEXT, text.as_bytes())],
+        }
+    }
+
+    /// Contents as given to the background process serving them, and as kept by the file backend, every flavor
+    /// being written as its MIME type and length on a line each, followed by its data.
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for (mime_type, data) in self.flavors() {
+            encoded.extend_from_slice(format!("{}\n{}\n", mime_type, data.len()).as_bytes());
+            encoded.extend_from_slice(data);
+        }
+        encoded
+    }
+
+    /// Contents given by the copying process, as written by `encode`
+    fn decode(encoded: &[u8]) -> Result<Self> {
+        let mut flavors: HashMap<_, _> = decode_flavors(encoded)?.into_iter().collect();
+        let text = flavors.remove(TEXT).map(String::from_utf8).transpose()?;
+        Ok(match (flavors.remove(HTML), text) {
+            (Some(html), Some(text)) => Contents::Html {
+                html: String::from_utf8(html)?,
+                text,
+            },
+            (None, Some(text)) => Contents::Text(text),
+            (_, None) => match flavors.into_iter().next() {
+                Some((mime_type, data)) => Contents::Image { data, mime_type },
+                None => bail!("No contents"),
+            },
+        })
+    }
+}
+
+/// Flavors of the contents written by `Contents::encode`, by MIME type, in order
+fn decode_flavors(mut encoded: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut flavors = Vec::new();
+    while !encoded.is_empty() {
+        let mut fields = encoded.splitn(3, |&byte| byte == b'\n');
+        let (Some(mime_type), Some(len), Some(rest)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("Truncated contents");
+        };
+        let len: usize = String::from_utf8_lossy(len).parse()?;
+        if rest.len() < len {
+            bail!("Truncated contents");
+        }
+        flavors.push((String::from_utf8(mime_type.to_vec())?, rest[..len].to_vec()));
+        encoded = &rest[len..];
+    }
+    Ok(flavors)
+}
+
+/// Line endings text is converted to when copied or pasted, such as between Windows and Unix systems
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// `text` with every line ended by a line feed, alone or after a carriage return, ending with this instead.
+    ///
+    /// Carriage returns not followed by a line feed are kept, and so is a last line without an ending.
+    pub fn convert(self, text: &[u8]) -> Vec<u8> {
+        let ending: &[u8] = match self {
+            Self::Lf => b"\n",
+            Self::Crlf => b"\r\n",
+        };
+
+        let mut converted = Vec::with_capacity(text.len());
+        let mut lines = text.split(|&byte| byte == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            if lines.peek().is_none() {
+                converted.extend_from_slice(line);
+                break;
+            }
+            converted.extend_from_slice(line.strip_suffix(b"\r").unwrap_or(line));
+            converted.extend_from_slice(ending);
+        }
+        converted
+    }
+}
+
+/// How copied contents are served, on X11 and Wayland where they are held by the process that copied them
+/// instead of the clipboard itself, and how long they are kept.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Serving {
+    /// Serve the contents from the current process, until other contents are copied,
+    /// instead of from a background one
+    pub foreground: bool,
+    /// Clear the contents after the duration, unless other contents were copied meanwhile
+    pub expire: Option<Duration>,
+    /// Whether the current process is the background one, telling the copying one once the contents are served
+    pub detached: bool,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Backend {
+    /// Name of the backend, as given in logs and errors
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Auto => "auto",
+            Backend::Wayland => "Wayland",
+            Backend::X11 => "X11",
+            Backend::Osc52 => "OSC 52",
+            Backend::File => "file",
+        }
+    }
+
+    /// Clipboards tried in turn, `terminal` telling whether OSC 52 may be used when there is no display,
+    /// before the file backend that always works.
+    fn candidates(self, terminal: bool) -> Vec<Backend> {
+        match self {
+            Backend::Auto => {
+                let mut candidates = Vec::new();
+                if env::var_os("WAYLAND_DISPLAY").is_some_and(|display| !display.is_empty()) {
+                    candidates.push(Backend::Wayland);
+                }
+                candidates.push(Backend::X11);
+                // over SSH there is no display, but the terminal of the user may still set its clipboard
+                if terminal && stderr().is_terminal() {
+                    candidates.push(Backend::Osc52);
+                }
+                // so that copy and paste at least work together, as in containers
+                candidates.push(Backend::File);
+                candidates
+            }
+            backend => vec![backend],
+        }
+    }
+
+    /// Whether copied contents are held by the process that copied them, and have to be served until replaced
+    fn is_served(self) -> bool {
+        cfg!(all(unix, not(target_os = "macos"))) && matches!(self, Backend::Wayland | Backend::X11)
+    }
+}
+
+/// Clipboard copied to and pasted from, provided by a single backend or by the first candidate backend that works
+pub trait Clipboard {
+    /// Name of the backend, as given in logs and errors
+    fn backend_name(&self) -> &str;
+
+    /// Puts `contents` on the clipboard
+    fn set_contents(&self, contents: &Contents) -> Result<()>;
+
+    /// Empties the clipboard
+    fn clear(&self) -> Result<()>;
+
+    /// Contents of the clipboard as the given MIME type, or `None` if they aren't available as such
+    fn get_target(&self, mime_type: &str) -> Result<Option<Vec<u8>>>;
+
+    /// MIME types the contents of the clipboard are available as, in the order given by the client that copied them
+    fn get_targets(&self) -> Result<Vec<String>>;
+
+    /// Puts `text` on the clipboard
+    fn set_text(&self, text: &str) -> Result<()> {
+        self.set_contents(&Contents::Text(text.to_string()))
+    }
+
+    /// Text on the clipboard as it was copied, empty if there is none
+    fn get_text(&self) -> Result<Vec<u8>> {
+        Ok(self.get_target(TEXT)?.unwrap_or_default())
+    }
+
+    /// PNG image on the clipboard, or `None` if it doesn't hold one, such as when text was copied
+    fn get_image(&self) -> Result<Option<Vec<u8>>> {
+        self.get_target(PNG)
+    }
+}
+
+/// Clipboard of the given backend, every candidate backend being tried in turn automatically
+#[derive(Debug, Clone, Copy)]
+pub struct System {
+    backend: Backend,
+    serving: Serving,
+    timeout: Duration,
+}
+
+impl System {
+    /// Clipboard of `backend`, copied contents being served as `serving` tells, and backends not responding
+    /// within `timeout` being given up on
+    pub fn new(backend: Backend, serving: Serving, timeout: Duration) -> Self {
+        Self {
+            backend,
+            serving,
+            timeout,
+        }
+    }
+
+    /// Clipboard of the single backend `candidate`
+    fn candidate(&self, candidate: Backend) -> Box<dyn Clipboard> {
+        let (serving, timeout) = (self.serving, self.timeout);
+        match candidate {
+            Backend::Wayland => Box::new(Wayland { serving, timeout }),
+            Backend::X11 => Box::new(X11 { serving, timeout }),
+            Backend::Osc52 => Box::new(Osc52 { serving }),
+            Backend::File => Box::new(File { serving, timeout }),
+            Backend::Auto => unreachable!(),
+        }
+    }
+
+    /// Runs `set` with the first candidate backend it succeeds with, `terminal` telling whether OSC 52 is one of them,
+    /// and `action` naming what it does in logs
+    fn copy(
+        &self,
+        terminal: bool,
+        action: &str,
+        set: impl Fn(&dyn Clipboard) -> Result<()>,
+    ) -> Result<()> {
+        let mut errors = Vec::new();
+        for candidate in self.backend.candidates(terminal) {
+            let clipboard = self.candidate(candidate);
+            let name = clipboard.backend_name();
+            match set(clipboard.as_ref()) {
+                Ok(()) if errors.is_empty() => {
+                    debug!("Used the {} backend to {}", name, action);
+                    return Ok(());
+                }
+                Ok(()) => {
+                    info!("Used the {} backend to {}", name, action);
+                    return Ok(());
+                }
+                Err(err) => {
+                    debug!("Failed to {} with the {} backend: {}", action, name, err);
+                    errors.push(format!("{}: {}", name, err));
+                }
+            }
+        }
+
+        bail!("{}", errors.join(", "));
+    }
+
+    /// Result of `get` with the first candidate backend it succeeds with
+    fn paste<T>(&self, get: impl Fn(&dyn Clipboard) -> Result<T>) -> Result<T> {
+        if self.backend == Backend::Osc52 {
+            bail!("Pasting isn't supported with OSC 52, which can only copy");
+        }
+
+        let mut errors = Vec::new();
+        for candidate in self.backend.candidates(false) {
+            let clipboard = self.candidate(candidate);
+            let name = clipboard.backend_name();
+            match get(clipboard.as_ref()) {
+                Ok(contents) if errors.is_empty() => {
+                    debug!("Pasted with the {} backend", name);
+                    return Ok(contents);
+                }
+                Ok(contents) => {
+                    info!("Pasted with the {} backend", name);
+                    return Ok(contents);
+                }
+                Err(err) => {
+                    debug!("Failed to paste with the {} backend: {}", name, err);
+                    errors.push(format!("{}: {}", name, err));
+                }
+            }
+        }
+
+        bail!(
+            "No clipboard backend available, as in headless sessions: {}",
+            errors.join(", ")
+        );
+    }
+}
+
+impl Clipboard for System {
+    fn backend_name(&self) -> &str {
+        self.backend.name()
+    }
+
+    fn set_contents(&self, contents: &Contents) -> Result<()> {
+        // images can't be copied through the terminal
+        let text = !matches!(contents, Contents::Image { .. });
+        self.copy(text, "copy", |clipboard| clipboard.set_contents(contents))
+            .map_err(|err| eyre!("Failed to set clipboard contents: {}", err))
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.copy(true, "clear", |clipboard| clipboard.clear())
+            .map_err(|err| eyre!("Failed to clear the clipboard: {}", err))
+    }
+
+    fn get_target(&self, mime_type: &str) -> Result<Option<Vec<u8>>> {
+        self.paste(|clipboard| clipboard.get_target(mime_type))
+    }
+
+    fn get_targets(&self) -> Result<Vec<String>> {
+        self.paste(|clipboard| clipboard.get_targets())
+    }
+
+    fn get_text(&self) -> Result<Vec<u8>> {
+        self.paste(|clipboard| clipboard.get_text())
+    }
+}
+
+/// Wayland compositor supporting the data control protocol
+struct Wayland {
+    serving: Serving,
+    timeout: Duration,
+}
+
+impl Clipboard for Wayland {
+    fn backend_name(&self) -> &str {
+        Backend::Wayland.name()
+    }
+
+    fn set_contents(&self, contents: &Contents) -> Result<()> {
+        set(Backend::Wayland, contents, self.serving, self.timeout)
+    }
+
+    fn clear(&self) -> Result<()> {
+        with_timeout(self.timeout, wayland::clear)
+    }
+
+    fn get_target(&self, mime_type: &str) -> Result<Option<Vec<u8>>> {
+        let mime_type = mime_type.to_string();
+        with_timeout(self.timeout, move || wayland::get_target(&mime_type))
+    }
+
+    fn get_targets(&self) -> Result<Vec<String>> {
+        with_timeout(self.timeout, wayland::get_targets)
+    }
+}
+
+/// X11 server, or the native clipboard outside of Linux and BSDs
+struct X11 {
+    serving: Serving,
+    timeout: Duration,
+}
+
+impl Clipboard for X11 {
+    fn backend_name(&self) -> &str {
+        Backend::X11.name()
+    }
+
+    fn set_contents(&self, contents: &Contents) -> Result<()> {
+        set(Backend::X11, contents, self.serving, self.timeout)
+    }
+
+    fn clear(&self) -> Result<()> {
+        with_timeout(self.timeout, x11::clear)
+    }
+
+    fn get_target(&self, mime_type: &str) -> Result<Option<Vec<u8>>> {
+        let mime_type = mime_type.to_string();
+        with_timeout(self.timeout, move || x11::get_target(&mime_type))
+    }
+
+    fn get_targets(&self) -> Result<Vec<String>> {
+        with_timeout(self.timeout, x11::get_targets)
+    }
+
+    fn get_text(&self) -> Result<Vec<u8>> {
+        with_timeout(self.timeout, x11::get_contents)
+    }
+}
+
+/// Terminal escape sequence, only able to copy
+struct Osc52 {
+    serving: Serving,
+}
+
+impl Clipboard for Osc52 {
+    fn backend_name(&self) -> &str {
+        Backend::Osc52.name()
+    }
+
+    fn set_contents(&self, contents: &Contents) -> Result<()> {
+        if self.serving.expire.is_some() {
+            warn!(
+                "Contents copied through the terminal don't expire, as whether they were replaced can't be told"
+            );
+        }
+        osc52(contents)
+    }
+
+    fn clear(&self) -> Result<()> {
+        osc52_sequence("!")
+    }
+
+    fn get_target(&self, _mime_type: &str) -> Result<Option<Vec<u8>>> {
+        bail!("Pasting isn't supported with OSC 52, which can only copy");
+    }
+
+    fn get_targets(&self) -> Result<Vec<String>> {
+        bail!("Pasting isn't supported with OSC 52, which can only copy");
+    }
+}
+
+/// File under `$XDG_RUNTIME_DIR`, only shared with the processes of the user on the same machine
+struct File {
+    serving: Serving,
+    timeout: Duration,
+}
+
+impl Clipboard for File {
+    fn backend_name(&self) -> &str {
+        Backend::File.name()
+    }
+
+    fn set_contents(&self, contents: &Contents) -> Result<()> {
+        set(Backend::File, contents, self.serving, self.timeout)
+    }
+
+    fn clear(&self) -> Result<()> {
+        with_timeout(self.timeout, file::clear)
+    }
+
+    fn get_target(&self, mime_type: &str) -> Result<Option<Vec<u8>>> {
+        let mime_type = mime_type.to_string();
+        with_timeout(self.timeout, move || file::get_target(&mime_type))
+    }
+
+    fn get_targets(&self) -> Result<Vec<String>> {
+        with_timeout(self.timeout, file::get_targets)
+    }
+}
+
+/// Clipboard held in memory, for tests
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct Mock {
+    contents: std::cell::RefCell<Option<Contents>>,
+    /// Whether pasting fails, as without any clipboard
+    unavailable: bool,
+}
+
+#[cfg(test)]
+impl Mock {
+    /// Clipboard holding `contents`
+    pub fn with(contents: Contents) -> Self {
+        Self {
+            contents: std::cell::RefCell::new(Some(contents)),
+            unavailable: false,
+        }
+    }
+
+    /// Clipboard failing to paste, as when there is none
+    pub fn unavailable() -> Self {
+        Self {
+            unavailable: true,
+            ..Self::default()
+        }
+    }
+
+    /// Contents last copied, if not cleared since
+    pub fn contents(&self) -> Option<Contents> {
+        self.contents.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Clipboard for Mock {
+    fn backend_name(&self) -> &str {
+        "memory"
+    }
+
+    fn set_contents(&self, contents: &Contents) -> Result<()> {
+        *self.contents.borrow_mut() = Some(contents.clone());
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        *self.contents.borrow_mut() = None;
+        Ok(())
+    }
+
+    fn get_target(&self, mime_type: &str) -> Result<Option<Vec<u8>>> {
+        if self.unavailable {
+            bail!("No clipboard backend available");
+        }
+        Ok(self.contents.borrow().as_ref().and_then(|contents| {
+            contents
+                .flavors()
+                .into_iter()
+                .find(|(flavor, _)| *flavor == mime_type)
+                .map(|(_, data)| data.to_vec())
+        }))
+    }
+
+    fn get_targets(&self) -> Result<Vec<String>> {
+        if self.unavailable {
+            bail!("No clipboard backend available");
+        }
+        Ok(self
+            .contents
+            .borrow()
+            .iter()
+            .flat_map(|contents| {
+                contents
+                    .flavors()
+                    .into_iter()
+                    .map(|(mime_type, _)| mime_type.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+}
+
+/// Puts `contents` on the clipboard of `backend`, serving them when it doesn't hold them by itself,
+/// or when they expire to clear them
+fn set(backend: Backend, contents: &Contents, serving: Serving, timeout: Duration) -> Result<()> {
+    // contents held by the clipboard itself are cleared by a process waiting for them to expire
+    let waiting = backend.is_served() || serving.expire.is_some();
+    match waiting {
+        true if serving.foreground || serving.detached => {
+            serve(backend, contents, serving, timeout)
+        }
+        true => detach(backend, contents, serving, timeout),
+        false => set_held(backend, contents, timeout),
+    }
+}
+
+/// Puts `contents` on a clipboard that holds them by itself, without serving them
+fn set_held(backend: Backend, contents: &Contents, timeout: Duration) -> Result<()> {
+    let contents = contents.clone();
+    with_timeout(timeout, move || match backend {
+        Backend::Wayland => wayland::set_contents(&contents, &mut || {}),
+        Backend::X11 => x11::set_contents(&contents, &mut || {}),
+        Backend::File => file::set_contents(&contents),
+        Backend::Osc52 | Backend::Auto => unreachable!(),
+    })
+}
+
+/// Result of `f`, run on a thread of its own so that backends that hang, such as an X server that stopped
+/// responding, are given up on after `timeout`, the thread being left behind.
+fn with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(f());
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => bail!(
+            "Not responding, gave up after {} (--timeout)",
+            humantime::format_duration(timeout)
+        ),
+        Err(RecvTimeoutError::Disconnected) => bail!("Stopped unexpectedly"),
+    }
+}
+
+/// Serves the contents given on stdin by the copying process, as the background process started by `detach`
+pub fn serve_detached(backend: Backend, serving: Serving, timeout: Duration) -> Result<()> {
+    let mut encoded = Vec::new();
+    stdin().read_to_end(&mut encoded)?;
+    let contents = match Contents::decode(&encoded) {
+        Ok(contents) => contents,
+        Err(err) => {
+            println!("Invalid contents given to the background process: {}", err);
+            return Err(err);
+        }
+    };
+    serve(backend, &contents, serving, timeout)
+}
+
+/// Serves `contents` from the current process until other contents are copied, or until they expire,
+/// those held by the clipboard itself being put there at once and cleared once they expire if they weren't replaced.
+///
+/// The background process tells the one that spawned it whether the contents could be put on the clipboard
+/// within `timeout`, with a line on stdout, before serving them.
+fn serve(backend: Backend, contents: &Contents, serving: Serving, timeout: Duration) -> Result<()> {
+    // the clipboard is left empty once served contents stop being served
+    if backend.is_served()
+        && let Some(expire) = serving.expire
+    {
+        thread::spawn(move || {
+            thread::sleep(expire);
+            process::exit(0);
+        });
+    }
+
+    let mut ready = || {
+        if serving.detached {
+            let mut out = stdout().lock();
+            let _ = writeln!(out, "{}", READY);
+            let _ = out.flush();
+        }
+    };
+    let result = if backend.is_served() {
+        serve_owned(backend, contents.clone(), &mut ready, timeout)
+    } else {
+        set_held(backend, contents, timeout).map(|()| {
+            ready();
+            if let Some(expire) = serving.expire {
+                thread::sleep(expire);
+                expire_held(backend, contents, timeout);
+            }
+        })
+    };
+
+    if serving.detached
+        && let Err(err) = &result
+    {
+        println!("{}", err);
+    }
+    result
+}
+
+/// Serves `contents` on X11 or Wayland from a thread of its own, calling `ready` once they are on the clipboard
+/// and returning once other contents are copied, or failing if they aren't on the clipboard within `timeout`.
+fn serve_owned(
+    backend: Backend,
+    contents: Contents,
+    ready: &mut dyn FnMut(),
+    timeout: Duration,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let owned = sender.clone();
+    thread::spawn(move || {
+        let mut owned = || {
+            let _ = owned.send(Ok(()));
+        };
+        let result = match backend {
+            Backend::Wayland => wayland::set_contents(&contents, &mut owned),
+            _ => x11::set_contents(&contents, &mut owned),
+        };
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(())) => ready(),
+        Ok(Err(err)) => return Err(err),
+        Err(RecvTimeoutError::Timeout) => bail!(
+            "Not responding, gave up after {} (--timeout)",
+            humantime::format_duration(timeout)
+        ),
+        Err(RecvTimeoutError::Disconnected) => bail!("Stopped unexpectedly"),
+    }
+    receiver.recv().unwrap_or(Ok(()))
+}
+
+/// Clears `contents` from a clipboard that holds them by itself, unless other contents were copied since
+fn expire_held(backend: Backend, contents: &Contents, timeout: Duration) {
+    let clipboard = System::new(backend, Serving::default(), timeout);
+    let unchanged = contents.flavors().into_iter().any(|(mime_type, data)| {
+        clipboard
+            .get_target(mime_type)
+            .is_ok_and(|current| current.as_deref() == Some(data))
+    });
+    if !unchanged {
+        debug!("Not clearing expired contents, other contents were copied since");
+    } else if let Err(err) = clipboard.clear() {
+        debug!("Failed to clear expired contents: {}", err);
+    }
+}
+
+/// Serves `contents` from a background process, spawned as `copy --serve` and given the contents on stdin,
+/// returning once it tells the contents are on the clipboard.
+///
+/// It is left in a process group of its own, so that it isn't interrupted along with the terminal session.
+fn detach(
+    backend: Backend,
+    contents: &Contents,
+    serving: Serving,
+    timeout: Duration,
+) -> Result<()> {
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(["--quiet", "copy", "--serve", "--backend"])
+        .arg(backend.to_possible_value().unwrap().get_name())
+        .arg("--timeout")
+        .arg(humantime::format_duration(timeout).to_string());
+    if let Some(expire) = serving.expire {
+        command
+            .arg("--expire")
+            .arg(humantime::format_duration(expire).to_string());
+    }
+    command
+        .current_dir("/")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .process_group(0);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => bail!(
+            "Failed to start the background process serving the contents: {}",
+            err
+        ),
+    };
+    // closed once written, so that the whole contents are read
+    child.stdin.take().unwrap().write_all(&contents.encode())?;
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line)?;
+    match line.trim_end() {
+        READY => {
+            debug!(
+                "Serving the contents from background process {}",
+                child.id()
+            );
+            Ok(())
+        }
+        "" => {
+            let status = child.wait()?;
+            bail!(
+                "Background process serving the contents exited with {}",
+                status
+            );
+        }
+        err => bail!("{}", err),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod x11 {
+    use color_eyre::eyre::{Result, bail};
+    use copypasta::{ClipboardContext, ClipboardProvider};
+    use std::{
+        collections::HashMap,
+        thread,
+        time::{Duration, Instant},
+    };
+    use x11_clipboard::Clipboard;
+    use x11rb::{
+        COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME, NONE,
+        connection::{Connection, RequestConnection},
+        protocol::{
+            Event,
+            xproto::{
+                Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt, CreateWindowAux,
+                EventMask, PropMode, Property, SELECTION_NOTIFY_EVENT, SelectionNotifyEvent,
+                Window, WindowClass,
+            },
+        },
+        rust_connection::RustConnection,
+        wrapper::ConnectionExt as _,
+    };
+
+    use super::{Contents, TEXT};
+
+    /// MIME type of text asked for by most clients along with `UTF8_STRING`
+    const UTF8_TEXT: &str = "text/plain;charset=utf-8";
+
+    /// Targets of the clipboard holding text, listed as `text/plain`
+    const TEXT_TARGETS: [&str; 4] = ["UTF8_STRING", "STRING", "TEXT", "COMPOUND_TEXT"];
+
+    /// Time given to the client owning the clipboard to send its contents
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Puts `contents` on the clipboard as every one of its flavors, calling `ready` once they're there, and serves
+    /// them until another client owns the clipboard, as clipboard managers do to keep contents once they stop
+    /// being served.
+    ///
+    /// Contents larger than a request are sent in chunks, with the `INCR` mechanism of the ICCCM.
+    pub fn set_contents(contents: &Contents, ready: &mut dyn FnMut()) -> Result<()> {
+        let (connection, screen) = x11rb::connect(None)?;
+        let atom = |name: &str| -> Result<Atom> {
+            Ok(connection
+                .intern_atom(false, name.as_bytes())?
+                .reply()?
+                .atom)
+        };
+        let (clipboard, targets, incr) = (atom("CLIPBOARD")?, atom("TARGETS")?, atom("INCR")?);
+
+        // text is offered as every target it's asked for by clients
+        let mut offered = Vec::new();
+        for (mime_type, data) in contents.flavors() {
+            if mime_type == TEXT {
+                for target in TEXT_TARGETS.iter().chain(&[UTF8_TEXT, TEXT]) {
+                    offered.push((atom(target)?, data));
+                }
+            } else {
+                offered.push((atom(mime_type)?, data));
+            }
+        }
+
+        own(&connection, screen, clipboard)?;
+        ready();
+
+        let chunk = connection.maximum_request_bytes() - 24;
+        // data left to send to requestors, by their window and property, with the target they asked for
+        let mut transfers: HashMap<(Window, Atom), (Atom, &[u8])> = HashMap::new();
+        let mut owner = true;
+        while owner || !transfers.is_empty() {
+            match connection.wait_for_event()? {
+                Event::SelectionRequest(request) => {
+                    // obsolete clients give no property to put the contents in
+                    let property = match request.property {
+                        NONE => request.target,
+                        property => property,
+                    };
+                    let offer = offered.iter().find(|(target, _)| *target == request.target);
+                    let granted = if request.target == targets {
+                        let mut atoms = vec![targets];
+                        atoms.extend(offered.iter().map(|(target, _)| *target));
+                        connection.change_property32(
+                            PropMode::REPLACE,
+                            request.requestor,
+                            property,
+                            AtomEnum::ATOM,
+                            &atoms,
+                        )?;
+                        true
+                    } else if let Some(&(target, data)) = offer {
+                        if data.len() <= chunk {
+                            connection.change_property8(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                target,
+                                data,
+                            )?;
+                        } else {
+                            // the requestor asks for every chunk by deleting the previous one
+                            connection.change_window_attributes(
+                                request.requestor,
+                                &ChangeWindowAttributesAux::new()
+                                    .event_mask(EventMask::PROPERTY_CHANGE),
+                            )?;
+                            connection.change_property32(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                incr,
+                                &[data.len() as u32],
+                            )?;
+                            transfers.insert((request.requestor, property), (target, data));
+                        }
+                        true
+                    } else {
+                        false
+                    };
+
+                    let notify = SelectionNotifyEvent {
+                        response_type: SELECTION_NOTIFY_EVENT,
+                        sequence: 0,
+                        time: request.time,
+                        requestor: request.requestor,
+                        selection: request.selection,
+                        target: request.target,
+                        property: if granted { property } else { NONE },
+                    };
+                    connection.send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+                    connection.flush()?;
+                }
+                Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                    let key = (event.window, event.atom);
+                    let Some((target, data)) = transfers.get_mut(&key) else {
+                        continue;
+                    };
+                    let (sent, rest) = data.split_at(data.len().min(chunk));
+                    connection.change_property8(
+                        PropMode::REPLACE,
+                        event.window,
+                        event.atom,
+                        *target,
+                        sent,
+                    )?;
+                    connection.flush()?;
+                    // an empty chunk ends the transfer
+                    if sent.is_empty() {
+                        transfers.remove(&key);
+                    } else {
+                        *data = rest;
+                    }
+                }
+                Event::SelectionClear(event) if event.selection == clipboard => owner = false,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Empties the clipboard by owning it without offering anything, the clipboard being left without an owner
+    /// once the connection is closed.
+    pub fn clear() -> Result<()> {
+        let (connection, screen) = x11rb::connect(None)?;
+        let clipboard = connection.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+        own(&connection, screen, clipboard)?;
+        Ok(())
+    }
+
+    /// Makes a window of its own the owner of `clipboard`, giving the window
+    fn own(connection: &RustConnection, screen: usize, clipboard: Atom) -> Result<Window> {
+        let root = connection.setup().roots[screen].root;
+        let window = connection.generate_id()?;
+        connection
+            .create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )?
+            .check()?;
+        connection
+            .set_selection_owner(window, clipboard, CURRENT_TIME)?
+            .check()?;
+        if connection.get_selection_owner(clipboard)?.reply()?.owner != window {
+            bail!("Failed to take over the clipboard");
+        }
+        Ok(window)
+    }
+
+    pub fn get_contents() -> Result<Vec<u8>> {
+        match ClipboardContext::new().and_then(|mut ctx| ctx.get_contents()) {
+            Ok(contents) => Ok(contents.into_bytes()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Clipboard contents as `mime_type`, `None` when its owner can't provide them as such, or when nothing was copied
+    pub fn get_target(mime_type: &str) -> Result<Option<Vec<u8>>> {
+        let clipboard = match Clipboard::new() {
+            Ok(clipboard) => clipboard,
+            Err(err) => bail!("{}", err),
+        };
+        let getter = &clipboard.getter;
+        let target = match mime_type {
+            TEXT => Ok(getter.atoms.utf8_string),
+            mime_type => getter.get_atom(mime_type),
+        };
+        let target = match target {
+            Ok(atom) => atom,
+            Err(err) => bail!("{}", err),
+        };
+        match clipboard.load(
+            getter.atoms.clipboard,
+            target,
+            getter.atoms.property,
+            TIMEOUT,
+        ) {
+            Ok(contents) => Ok((!contents.is_empty()).then_some(contents)),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// MIME types the clipboard owner offers its contents as, asked with the `TARGETS` target, which also lists
+    /// X11 specific ones that are left out but for the text ones, listed as `text/plain`.
+    pub fn get_targets() -> Result<Vec<String>> {
+        let clipboard = match Clipboard::new() {
+            Ok(clipboard) => clipboard,
+            Err(err) => bail!("{}", err),
+        };
+        let getter = &clipboard.getter;
+        let (connection, atoms) = (&getter.connection, &getter.atoms);
+        connection
+            .convert_selection(
+                getter.window,
+                atoms.clipboard,
+                atoms.targets,
+                atoms.property,
+                CURRENT_TIME,
+            )?
+            .check()?;
+        connection.flush()?;
+
+        // the reply of `TARGETS` is a list of atoms, which `Clipboard::load` refuses as not of the type asked
+        let start = Instant::now();
+        let event = loop {
+            match connection.poll_for_event()? {
+                Some(Event::SelectionNotify(event)) if event.selection == atoms.clipboard => {
+                    break event;
+                }
+                Some(_) => {}
+                None if start.elapsed() > TIMEOUT => {
+                    bail!("Timed out waiting for the clipboard owner")
+                }
+                None => thread::sleep(Duration::from_millis(20)),
+            }
+        };
+        // nothing was copied
+        if event.property == u32::from(AtomEnum::NONE) {
+            return Ok(Vec::new());
+        }
+
+        let reply = connection
+            .get_property(
+                true,
+                getter.window,
+                event.property,
+                AtomEnum::ATOM,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        let mut targets = Vec::new();
+        for atom in reply.value32().into_iter().flatten() {
+            let name = connection.get_atom_name(atom)?.reply()?.name;
+            let name = String::from_utf8_lossy(&name);
+            let target = match name.as_ref() {
+                name if TEXT_TARGETS.contains(&name) => TEXT.to_string(),
+                name if name.contains('/') => name.to_string(),
+                _ => continue,
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        Ok(targets)
+    }
+}
+
+/// The native clipboard, keeping copied contents by itself
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod x11 {
+    use color_eyre::eyre::{Result, bail};
+    use copypasta::{ClipboardContext, ClipboardProvider};
+
+    use super::{Contents, TEXT};
+
+    pub fn set_contents(contents: &Contents, ready: &mut dyn FnMut()) -> Result<()> {
+        // rich text is copied as plain text only
+        let (Contents::Text(text) | Contents::Html { text, .. }) = contents else {
+            bail!("Copying images isn't supported on this platform");
+        };
+        match ClipboardContext::new().and_then(|mut ctx| ctx.set_contents(text.clone())) {
+            Ok(()) => {
+                ready();
+                Ok(())
+            }
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    pub fn clear() -> Result<()> {
+        match ClipboardContext::new().and_then(|mut ctx| ctx.set_contents(String::new())) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    pub fn get_contents() -> Result<Vec<u8>> {
+        match ClipboardContext::new().and_then(|mut ctx| ctx.get_contents()) {
+            Ok(contents) => Ok(contents.into_bytes()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Only text can be pasted from the native clipboard
+    pub fn get_target(mime_type: &str) -> Result<Option<Vec<u8>>> {
+        if mime_type != TEXT {
+            bail!("Only {} can be pasted on this platform", TEXT);
+        }
+        let contents = get_contents()?;
+        Ok((!contents.is_empty()).then_some(contents))
+    }
+
+    pub fn get_targets() -> Result<Vec<String>> {
+        Ok(vec![TEXT.to_string()])
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod wayland {
+    use color_eyre::eyre::{Result, bail};
+    use std::io::Read;
+    use wl_clipboard_rs::{copy, paste};
+
+    use super::{Contents, TEXT};
+
+    /// Puts `contents` on the clipboard, calling `ready` once they're there, and serves them until other contents are copied
+    pub fn set_contents(contents: &Contents, ready: &mut dyn FnMut()) -> Result<()> {
+        let sources = contents
+            .flavors()
+            .into_iter()
+            .map(|(mime_type, data)| copy::MimeSource {
+                source: copy::Source::Bytes(data.into()),
+                // also offered as the other types of text, such as `UTF8_STRING`
+                mime_type: match mime_type {
+                    TEXT => copy::MimeType::Text,
+                    mime_type => copy::MimeType::Specific(mime_type.to_string()),
+                },
+            })
+            .collect();
+        let mut options = copy::Options::new();
+        options.foreground(true);
+        let prepared = match options.prepare_copy_multi(sources) {
+            Ok(prepared) => prepared,
+            Err(err) => bail!("{}", err),
+        };
+        ready();
+
+        match prepared.serve() {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    pub fn clear() -> Result<()> {
+        match copy::clear(copy::ClipboardType::Regular, copy::Seat::All) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Clipboard contents as `mime_type`, `None` when they aren't offered as such
+    pub fn get_target(mime_type: &str) -> Result<Option<Vec<u8>>> {
+        read(match mime_type {
+            // also offered as `text/plain;charset=utf-8` or `UTF8_STRING`
+            TEXT => paste::MimeType::Text,
+            mime_type => paste::MimeType::Specific(mime_type),
+        })
+    }
+
+    pub fn get_targets() -> Result<Vec<String>> {
+        match paste::get_mime_types_ordered(paste::ClipboardType::Regular, paste::Seat::Unspecified)
+        {
+            Ok(targets) => Ok(targets),
+            Err(paste::Error::NoSeats | paste::Error::ClipboardEmpty) => Ok(Vec::new()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Clipboard contents of the given type, `None` when nothing was copied or not as this type
+    fn read(mime_type: paste::MimeType) -> Result<Option<Vec<u8>>> {
+        let contents = paste::get_contents(
+            paste::ClipboardType::Regular,
+            paste::Seat::Unspecified,
+            mime_type,
+        );
+        let mut reader = match contents {
+            Ok((reader, _)) => reader,
+            Err(
+                paste::Error::NoSeats | paste::Error::ClipboardEmpty | paste::Error::NoMimeType,
+            ) => return Ok(None),
+            Err(err) => bail!("{}", err),
+        };
+
+        let mut contents = Vec::new();
+        if let Err(err) = reader.read_to_end(&mut contents) {
+            bail!("Failed to read clipboard contents: {}", err);
+        }
+        Ok(Some(contents))
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod wayland {
+    use color_eyre::eyre::{Result, bail};
+
+    use super::Contents;
+
+    pub fn set_contents(_contents: &Contents, _ready: &mut dyn FnMut()) -> Result<()> {
+        bail!("Wayland isn't supported on this platform");
+    }
+
+    pub fn clear() -> Result<()> {
+        bail!("Wayland isn't supported on this platform");
+    }
+
+    pub fn get_target(_mime_type: &str) -> Result<Option<Vec<u8>>> {
+        bail!("Wayland isn't supported on this platform");
+    }
+
+    pub fn get_targets() -> Result<Vec<String>> {
+        bail!("Wayland isn't supported on this platform");
+    }
+}
+
+/// Clipboard kept in a file of the runtime directory of the user, when there is no other one
+mod file {
+    use color_eyre::eyre::{Result, bail};
+    use std::{
+        env,
+        fs::{DirBuilder, OpenOptions, read, remove_file, rename},
+        io::{ErrorKind, Write},
+        os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+        path::PathBuf,
+        process,
+    };
+
+    use super::{Contents, decode_flavors};
+
+    /// `$XDG_RUNTIME_DIR/dsu-clipboard`, only accessible to the user
+    fn directory() -> Result<PathBuf> {
+        match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => Ok(dir.join("dsu-clipboard")),
+            _ => bail!("XDG_RUNTIME_DIR isn't set"),
+        }
+    }
+
+    /// Writes `contents` as a whole, replacing the previous ones at once, so that pastes never read half of them
+    pub fn set_contents(contents: &Contents) -> Result<()> {
+        let dir = directory()?;
+        if let Err(err) = DirBuilder::new().recursive(true).mode(0o700).create(&dir) {
+            bail!("Failed to create {:?}: {}", dir, err);
+        }
+
+        let (path, temporary) = (
+            dir.join("contents"),
+            dir.join(format!("contents.{}", process::id())),
+        );
+        let written = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary)
+            .and_then(|mut file| file.write_all(&contents.encode()))
+            .and_then(|()| rename(&temporary, &path));
+        if let Err(err) = written {
+            let _ = remove_file(&temporary);
+            bail!("Failed to write {:?}: {}", path, err);
+        }
+        Ok(())
+    }
+
+    pub fn clear() -> Result<()> {
+        let path = directory()?.join("contents");
+        match remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => bail!("Failed to remove {:?}: {}", path, err),
+        }
+    }
+
+    /// Flavors of the contents last copied, none if nothing was
+    fn flavors() -> Result<Vec<(String, Vec<u8>)>> {
+        let path = directory()?.join("contents");
+        match read(&path) {
+            Ok(encoded) => decode_flavors(&encoded),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => bail!("Failed to read {:?}: {}", path, err),
+        }
+    }
+
+    pub fn get_target(mime_type: &str) -> Result<Option<Vec<u8>>> {
+        Ok(flavors()?
+            .into_iter()
+            .find(|(flavor, _)| flavor == mime_type)
+            .map(|(_, data)| data))
+    }
+
+    pub fn get_targets() -> Result<Vec<String>> {
+        Ok(flavors()?
+            .into_iter()
+            .map(|(mime_type, _)| mime_type)
+            .collect())
+    }
+}
+
+/// Asks the terminal to put `contents` on its clipboard with an OSC 52 escape sequence written to the controlling terminal,
+/// passed through to the outer terminal within tmux and GNU screen.
+///
+/// Terminals that don't support it, or don't allow it, silently ignore the sequence.
+fn osc52(contents: &Contents) -> Result<()> {
+    // rich text is copied as plain text only
+    let (Contents::Text(text) | Contents::Html { text, .. }) = contents else {
+        bail!("Only text can be copied through the terminal");
+    };
+    let encoded = STANDARD.encode(text);
+    if encoded.len() > OSC52_LIMIT {
+        warn!(
+            "Copied text takes {} bytes once encoded, some terminals ignore OSC 52 sequences over {} bytes",
+            encoded.len(),
+            OSC52_LIMIT
+        );
+    }
+
+    osc52_sequence(&encoded)
+}
+
+/// Writes the OSC 52 sequence setting the clipboard to `data` to the terminal, wrapped to be passed through
+/// terminal multiplexers, `data` being base64 or `!` to clear the clipboard.
+fn osc52_sequence(data: &str) -> Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", data);
+    let sequence = if env::var_os("TMUX").is_some() {
+        // escapes within the passthrough sequence are doubled
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else if env::var_os("STY").is_some() {
+        sequence
+            .as_bytes()
+            .chunks(SCREEN_CHUNK)
+            .map(|chunk| format!("\x1bP{}\x1b\\", String::from_utf8_lossy(chunk)))
+            .collect()
+    } else {
+        sequence
+    };
+
+    let mut tty = match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(err) => bail!("Failed to open the terminal: {}", err),
+    };
+    if let Err(err) = tty
+        .write_all(sequence.as_bytes())
+        .and_then(|()| tty.flush())
+    {
+        bail!("Failed to write to the terminal: {}", err);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_line_endings() {
+        for (text, lf, crlf) in [
+            (&b""[..], &b""[..], &b""[..]),
+            (b"a", b"a", b"a"),
+            (b"a\n", b"a\n", b"a\r\n"),
+            (b"a\r\n", b"a\n", b"a\r\n"),
+            (b"a\nb\r\nc", b"a\nb\nc", b"a\r\nb\r\nc"),
+            (b"\n\r\n\n", b"\n\n\n", b"\r\n\r\n\r\n"),
+            // lone carriage returns are kept, as in old Mac line endings
+            (b"a\rb", b"a\rb", b"a\rb"),
+            (b"a\r", b"a\r", b"a\r"),
+            (b"a\r\r\n", b"a\r\n", b"a\r\r\n"),
+        ] {
+            assert_eq!(
+                LineEnding::Lf.convert(text),
+                lf,
+                "converting {text:?} to LF"
+            );
+            assert_eq!(
+                LineEnding::Crlf.convert(text),
+                crlf,
+                "converting {text:?} to CRLF"
+            );
+        }
+    }
+
+    #[test]
+    fn converts_line_endings_once() {
+        for text in [&b"a\r\nb\r\n"[..], b"a\nb\r\nc\r", b"\r\n\r\n"] {
+            for ending in [LineEnding::Lf, LineEnding::Crlf] {
+                let converted = ending.convert(text);
+                assert_eq!(
+                    ending.convert(&converted),
+                    converted,
+                    "converting {text:?} twice"
+                );
+            }
+        }
+        assert_eq!(LineEnding::Crlf.convert(b"a\r\nb\r\n"), b"a\r\nb\r\n");
+    }
+}
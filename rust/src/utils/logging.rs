@@ -0,0 +1,99 @@
+use std::{
+    fmt,
+    io::{Write, stderr},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::{Event, Level, Subscriber, level_filters::LevelFilter};
+use tracing_subscriber::{
+    fmt::{FmtContext, FormatEvent, FormatFields, format::Writer},
+    registry::LookupSpan,
+};
+
+use crate::cli::{Color, Verbosity};
+use crate::utils::style::{Style, Styler};
+
+/// Whether a status line is shown at the bottom of stderr, to be cleared before other messages
+static STATUS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Plain message format for users rather than log files: no timestamps nor targets,
+/// and only messages other than informational ones are prefixed with their level.
+struct Format {
+    styler: Styler,
+}
+
+impl<S, N> FormatEvent<S, N> for Format
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // messages take the place of the status line, which is drawn again below them
+        if STATUS_SHOWN.swap(false, Ordering::Relaxed) {
+            write!(writer, "\r\x1b[K")?;
+        }
+
+        let prefix = match *event.metadata().level() {
+            Level::ERROR => Some(("ERROR:", Style::Red)),
+            Level::WARN => Some(("WARN:", Style::Yellow)),
+            Level::INFO => None,
+            Level::DEBUG | Level::TRACE => Some(("DEBUG:", Style::Dim)),
+        };
+
+        if let Some((prefix, style)) = prefix {
+            write!(writer, "{} ", self.styler.paint(prefix, style))?;
+        }
+
+        ctx.format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Installs the global subscriber, writing to stderr so that stdout is left for command output.
+///
+/// Without an explicit verbosity, `--quiet` only lets errors through.
+pub fn init(verbosity: Option<Verbosity>, quiet: bool, color: Color) {
+    let level = match verbosity.unwrap_or(if quiet {
+        Verbosity::Error
+    } else {
+        Verbosity::Info
+    }) {
+        Verbosity::Off => LevelFilter::OFF,
+        Verbosity::Error => LevelFilter::ERROR,
+        Verbosity::Warn => LevelFilter::WARN,
+        Verbosity::Info => LevelFilter::INFO,
+        Verbosity::Debug => LevelFilter::DEBUG,
+    };
+
+    let styler = Styler::new(color, &stderr());
+
+    tracing_subscriber::fmt()
+        .with_writer(stderr)
+        .with_ansi(styler.enabled())
+        .with_max_level(level)
+        .event_format(Format { styler })
+        .init();
+}
+
+/// Shows `line` as the status line of stderr, replacing the previous one.
+///
+/// Meant for terminals only, as the line is overwritten in place rather than ended.
+pub fn show_status(line: &str) {
+    let mut err = stderr().lock();
+    let _ = write!(err, "\r\x1b[K{}", line);
+    let _ = err.flush();
+    STATUS_SHOWN.store(true, Ordering::Relaxed);
+}
+
+/// Clears the status line of stderr, if one is shown
+pub fn clear_status() {
+    if STATUS_SHOWN.swap(false, Ordering::Relaxed) {
+        let mut err = stderr().lock();
+        let _ = write!(err, "\r\x1b[K");
+        let _ = err.flush();
+    }
+}
@@ -0,0 +1,58 @@
+use color_eyre::eyre::{Result, eyre};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Set of glob patterns matched against either the filename or the relative path of an entry
+///
+/// Patterns containing a `/` are matched against the path relative to the path being processed,
+/// supporting `**` to match any number of directories, all others against the filename.
+#[derive(Debug)]
+pub struct Matcher {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Matcher {
+    /// Compiles `patterns`, `None` if there are none to match
+    pub fn new(patterns: &[String]) -> Result<Option<Self>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|err| eyre!("Invalid glob pattern {:?}: {}", pattern, err))?;
+
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+
+        Ok(Some(Self {
+            names: names.build()?,
+            paths: paths.build()?,
+        }))
+    }
+
+    pub fn is_match(&self, filename: &Path, relative: &Path) -> bool {
+        self.names.is_match(filename) || self.paths.is_match(relative)
+    }
+}
+
+/// Filename and path relative to `root` that patterns are matched against
+pub fn match_names<'a>(root: &Path, path: &'a Path) -> (&'a Path, &'a Path) {
+    let filename = Path::new(path.file_name().unwrap_or(path.as_os_str()));
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        _ => filename,
+    };
+
+    (filename, relative)
+}
@@ -1 +1,6 @@
+pub mod clipboard;
 pub mod file_keeper;
+pub mod logging;
+pub mod matcher;
+pub mod size;
+pub mod style;
@@ -0,0 +1,172 @@
+/// Formats a number of bytes in the largest unit it reaches, with two decimals.
+///
+/// Binary units (KiB, MiB, ...) are powers of 1024, while `si` ones (KB, MB, ...) are powers of 1000.
+pub fn human_size(size: u64, si: bool) -> String {
+    let (base, units) = if si {
+        (1000.0, ["KB", "MB", "GB", "TB", "PB", "EB"])
+    } else {
+        (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"])
+    };
+
+    let mut value = size as f64;
+    if value < base {
+        return format!("{} B", size);
+    }
+
+    // values rounding up to the base are shown in the next unit, such as 1 MiB instead of 1024.00 KiB
+    let mut unit = units[0];
+    for next in units {
+        if (value * 100.0).round() < base * 100.0 {
+            break;
+        }
+        value /= base;
+        unit = next;
+    }
+
+    format!("{:.2} {}", value, unit)
+}
+
+/// Parses a size given as a number of bytes, optionally followed by a unit, such as `20G`, `1.5 TB` or `500MiB`.
+///
+/// Units are case insensitive, and bare or binary ones (`K`, `KiB`, ...) are powers of 1024,
+/// as in `du` and `ls`, while SI ones (`KB`, `MB`, ...) are powers of 1000.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => return Err(format!("invalid size: {:?}", value)),
+    };
+
+    let unit = unit.trim().to_ascii_lowercase();
+    let (prefix, base) = match unit.as_str() {
+        "" | "b" => ("", 1024.0),
+        unit => match unit.strip_suffix("ib") {
+            Some(prefix) => (prefix, 1024.0),
+            None => match unit.strip_suffix('b') {
+                Some(prefix) => (prefix, 1000.0),
+                None => (unit, 1024.0),
+            },
+        },
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => return Err(format!("invalid size unit: {:?}", unit)),
+    };
+
+    let size = number * f64::powi(base, exponent);
+    if size > u64::MAX as f64 {
+        return Err(format!("size too large: {:?}", value));
+    }
+
+    Ok(size as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIB: u64 = 1024;
+
+    #[test]
+    fn formats_binary_sizes() {
+        for (size, expected) in [
+            (0, "0 B"),
+            (1, "1 B"),
+            (KIB - 1, "1023 B"),
+            (KIB, "1.00 KiB"),
+            (KIB + 1, "1.00 KiB"),
+            (KIB * 3 / 2, "1.50 KiB"),
+            (KIB.pow(2) - 1, "1.00 MiB"),
+            (KIB.pow(2), "1.00 MiB"),
+            (KIB.pow(3) * 1023, "1023.00 GiB"),
+            (KIB.pow(4), "1.00 TiB"),
+            (KIB.pow(5), "1.00 PiB"),
+            (KIB.pow(6) - 1, "1.00 EiB"),
+            (KIB.pow(6), "1.00 EiB"),
+            (u64::MAX, "16.00 EiB"),
+        ] {
+            assert_eq!(human_size(size, false), expected, "formatting {size}");
+        }
+    }
+
+    #[test]
+    fn formats_si_sizes() {
+        for (size, expected) in [
+            (999, "999 B"),
+            (1000, "1.00 KB"),
+            (1024, "1.02 KB"),
+            (999_994, "999.99 KB"),
+            (999_995, "1.00 MB"),
+            (10u64.pow(12), "1.00 TB"),
+            (10u64.pow(18), "1.00 EB"),
+            (u64::MAX, "18.45 EB"),
+        ] {
+            assert_eq!(human_size(size, true), expected, "formatting {size}");
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        for (value, expected) in [
+            ("0", 0),
+            ("1023", 1023),
+            ("1024B", KIB),
+            ("1K", KIB),
+            ("1k", KIB),
+            ("1KiB", KIB),
+            ("1KB", 1000),
+            ("1.5 TB", 1_500_000_000_000),
+            ("500MiB", 500 * KIB.pow(2)),
+            (" 20G ", 20 * KIB.pow(3)),
+            ("1E", KIB.pow(6)),
+        ] {
+            assert_eq!(parse_size(value), Ok(expected), "parsing {value:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_sizes() {
+        for value in [
+            "",
+            "K",
+            "abc",
+            "1.2.3",
+            "-1",
+            "1X",
+            "1 KiBs",
+            "17E",
+            "1000000EB",
+        ] {
+            assert!(parse_size(value).is_err(), "parsing {value:?}");
+        }
+    }
+
+    #[test]
+    fn parses_formatted_sizes() {
+        for size in [
+            0,
+            1023,
+            KIB,
+            KIB * 3 / 2,
+            KIB.pow(2),
+            5 * KIB.pow(3),
+            KIB.pow(6),
+        ] {
+            assert_eq!(parse_size(&human_size(size, false)), Ok(size));
+        }
+        for size in [999, 1000, 1_500_000, 10u64.pow(12), 10u64.pow(18)] {
+            assert_eq!(parse_size(&human_size(size, true)), Ok(size));
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::{env, io::IsTerminal};
+
+use crate::cli::Color;
+
+/// Text style, as an ANSI escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Dim,
+    Red,
+    Yellow,
+    BoldBlue,
+    Reverse,
+}
+
+/// Styles text written to a stream, or leaves it plain when colors aren't used on it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Styler {
+    enabled: bool,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Dim => "2",
+            Style::Red => "31",
+            Style::Yellow => "33",
+            Style::BoldBlue => "1;34",
+            Style::Reverse => "7",
+        }
+    }
+}
+
+impl Styler {
+    /// Styler of `stream`, colors being used as chosen by `color`.
+    ///
+    /// Automatically, colors are only used on terminals and unless `NO_COLOR` or `DSU_NO_COLOR` is set.
+    pub fn new(color: Color, stream: &impl IsTerminal) -> Self {
+        let enabled = match color {
+            Color::On => true,
+            Color::Off => false,
+            Color::Auto => {
+                let disabled = |name| env::var_os(name).is_some_and(|value| !value.is_empty());
+                !disabled("NO_COLOR") && !disabled("DSU_NO_COLOR") && stream.is_terminal()
+            }
+        };
+
+        Self { enabled }
+    }
+
+    /// Whether colors are used
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `text` in the given style
+    pub fn paint(&self, text: impl AsRef<str>, style: Style) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", style.code(), text.as_ref())
+        } else {
+            text.as_ref().to_string()
+        }
+    }
+}